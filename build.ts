@@ -1,6 +1,9 @@
 import { promises as fs, createWriteStream } from 'fs';
 import path from 'path';
 import posixPath from 'node:path/posix';
+import zlib from 'zlib';
+
+const OUI_CSV_URL = "https://standards-oui.ieee.org/oui/oui.csv";
 
 async function main() {
     const webBuildPath = path.join(__dirname, "web", "dist");
@@ -10,6 +13,7 @@ async function main() {
     await fs.mkdir(rustAssetsPath, { recursive: true });
     await fs.cp(webBuildPath, rustAssetsPath, { recursive: true });
     await buildAssetsMap(rustPath);
+    await buildOuiMap(path.join(__dirname, "src", "vendor"));
 }
 main();
 
@@ -50,4 +54,56 @@ async function buildAssetsMap(p: string) {
     });
 }
 
+async function buildOuiMap(p: string) {
+    // one "XXXXXX\tOrganization" line per MA-L assignment, gzip compressed
+    let lines: string[] = [];
+    try {
+        const response = await fetch(OUI_CSV_URL);
+        if (!response.ok) throw new Error(`${response.status} ${response.statusText}`);
+        lines = parseOuiCsv(await response.text());
+    } catch (e) {
+        console.warn(`Unable to download ${OUI_CSV_URL}, building without vendor names: ${e}`);
+    }
+    await fs.writeFile(path.join(p, "oui.gz"), zlib.gzipSync(lines.join("\n"), { level: 9 }));
+    await fs.writeFile(path.join(p, "oui_map.rs"),
+        `pub const OUI_MAP: &[u8] = include_bytes!(r"oui.gz");
+`);
+}
+
+function parseOuiCsv(csv: string): string[] {
+    const lines: string[] = [];
+    for (const row of csv.split(/\r?\n/).slice(1)) {
+        const fields = splitCsvRow(row);
+        if (fields.length < 3 || fields[0] !== "MA-L") continue;
+        lines.push(`${fields[1].toUpperCase()}\t${fields[2].trim()}`);
+    }
+    return lines.sort();
+}
 
+function splitCsvRow(row: string): string[] {
+    const fields: string[] = [];
+    let field = "";
+    let quoted = false;
+    for (let i = 0; i < row.length; i++) {
+        const c = row[i];
+        if (quoted) {
+            if (c === '"' && row[i + 1] === '"') {
+                field += '"';
+                i++;
+            } else if (c === '"') {
+                quoted = false;
+            } else {
+                field += c;
+            }
+        } else if (c === '"') {
+            quoted = true;
+        } else if (c === ',') {
+            fields.push(field);
+            field = "";
+        } else {
+            field += c;
+        }
+    }
+    fields.push(field);
+    return fields;
+}
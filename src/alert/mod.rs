@@ -0,0 +1,300 @@
+//! Alert rules evaluated on every statistics tick, and their notifications.
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::Shared;
+use futures::FutureExt;
+use pnet::util::MacAddr;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use crate::statistics::{InterfaceStatistics, LastTick};
+
+mod rule;
+mod sink;
+
+pub use rule::Rule;
+use rule::{Condition, Track, Transition};
+pub use sink::{Sinks, Smtp, SyslogTarget};
+
+/// Alerts kept for `get_alerts`.
+const MAX_HISTORY: usize = 1024;
+/// MAC addresses remembered per `new_mac` rule and interface.
+const MAX_LEARNED_MACS: usize = 65536;
+/// Notifications waiting for the sinks, further ones are only recorded.
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Raised,
+    Cleared,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub interface: String,
+    /// The IP or MAC address the alert is about, for per host rules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub state: AlertState,
+    pub value: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    /// Milliseconds since start, like the history timestamps.
+    pub timestamp: u64,
+}
+
+impl Alert {
+    pub fn message(&self) -> String {
+        let state = match self.state {
+            AlertState::Raised => "raised",
+            AlertState::Cleared => "cleared",
+        };
+        let mut message = format!("{} {} on {}", self.rule, state, self.interface);
+        if let Some(subject) = &self.subject {
+            message += &format!(" for {}", subject);
+        }
+        message += &format!(": {}", self.value);
+        if let Some(threshold) = self.threshold {
+            message += &format!(" (threshold {})", threshold);
+        }
+        message
+    }
+}
+
+/// Rule, interface and subject of one alert.
+type Key = (usize, String, Option<String>);
+
+struct LearnedMacs {
+    since: u64,
+    macs: HashSet<MacAddr>,
+}
+
+#[derive(Default)]
+struct State {
+    tracks: HashMap<Key, Track>,
+    active: HashMap<Key, Alert>,
+    learned: HashMap<(usize, String), LearnedMacs>,
+    history: VecDeque<Alert>,
+}
+
+impl State {
+    fn feed(&mut self, key: &Key, rule: &Rule, high: bool, low: bool) -> Option<Transition> {
+        let track = self.tracks.entry(key.clone()).or_default();
+        let clear_after = rule.clear_after.unwrap_or(rule.raise_after);
+        let transition = track.feed(high, low, rule.raise_after, clear_after);
+        if track.is_idle() {
+            self.tracks.remove(key);
+        }
+        transition
+    }
+}
+
+pub struct Alerts {
+    rules: Vec<Rule>,
+    tx: mpsc::Sender<Alert>,
+    /// Fires once the notification task is done.
+    done: Shared<oneshot::Receiver<()>>,
+    state: Mutex<State>,
+}
+
+impl Alerts {
+    /// Spawns the task delivering notifications to `sinks`.
+    pub fn start(rules: Vec<Rule>, sinks: Sinks) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let (done_tx, done) = oneshot::channel();
+        tokio::spawn(async move {
+            sink::run(sinks, rx).await;
+            drop(done_tx);
+        });
+        Self {
+            rules,
+            tx,
+            done: done.shared(),
+            state: Default::default(),
+        }
+    }
+
+    /// Called by the ticker right after the interfaces were updated.
+    pub fn evaluate(&self, timestamp: u64, interfaces: &HashMap<String, InterfaceStatistics>) {
+        let mut state = self.state.lock().unwrap();
+        let mut alerts = Vec::new();
+        let ticks: Vec<(&String, LastTick)> = interfaces
+            .iter()
+            .map(|(name, statistics)| (name, statistics.last_tick()))
+            .collect();
+        for (index, rule) in self.rules.iter().enumerate() {
+            for (name, tick) in &ticks {
+                let name = *name;
+                if !rule.applies_to(name) {
+                    continue;
+                }
+                match &rule.condition {
+                    Condition::InterfaceRate { .. } => {
+                        let (above, below) = rule.thresholds().unwrap();
+                        let rate = tick.bytes as f64;
+                        let key = (index, name.clone(), None);
+                        if let Some(transition) = state.feed(&key, rule, rate > above, rate < below)
+                        {
+                            alerts.push((key, transition, rate));
+                        }
+                    }
+                    Condition::HostShare { .. } => {
+                        let (above, below) = rule.thresholds().unwrap();
+                        let mut shares: HashMap<String, f64> = HashMap::new();
+                        if tick.bytes > 0 {
+                            for (ip, bytes) in &tick.hosts {
+                                let share = *bytes as f64 * 100.0 / tick.bytes as f64;
+                                shares.insert(ip.to_string(), share);
+                            }
+                        }
+                        // hosts which went quiet count as no share at all
+                        let tracked: Vec<String> = state
+                            .tracks
+                            .keys()
+                            .filter(|(i, n, _)| *i == index && n == name)
+                            .filter_map(|(_, _, subject)| subject.clone())
+                            .collect();
+                        for subject in tracked {
+                            shares.entry(subject).or_insert(0.0);
+                        }
+                        for (subject, share) in shares {
+                            let key = (index, name.clone(), Some(subject));
+                            // only hosts beyond the threshold are tracked
+                            if share <= above && !state.tracks.contains_key(&key) {
+                                continue;
+                            }
+                            if let Some(transition) =
+                                state.feed(&key, rule, share > above, share < below)
+                            {
+                                alerts.push((key, transition, share));
+                            }
+                        }
+                    }
+                    Condition::NewMac { known, learn } => {
+                        let learned =
+                            state
+                                .learned
+                                .entry((index, name.clone()))
+                                .or_insert_with(|| LearnedMacs {
+                                    since: timestamp,
+                                    macs: known.iter().copied().collect(),
+                                });
+                        let learning = timestamp < learned.since + learn * 1000;
+                        for &mac in &tick.macs {
+                            if learned.macs.len() >= MAX_LEARNED_MACS {
+                                break;
+                            }
+                            if learned.macs.insert(mac) && !learning {
+                                alerts.push((
+                                    (index, name.clone(), Some(mac.to_string())),
+                                    Transition::Raised,
+                                    1.0,
+                                ));
+                            }
+                        }
+                    }
+                    Condition::CaptureStopped => {
+                        let stopped = !tick.listening;
+                        let key = (index, name.clone(), None);
+                        if let Some(transition) = state.feed(&key, rule, stopped, !stopped) {
+                            alerts.push((key, transition, stopped as u8 as f64));
+                        }
+                    }
+                }
+            }
+        }
+        // interfaces which were cleared take their alerts along, the sinks
+        // are told these are cleared with the last value they saw
+        state
+            .tracks
+            .retain(|(_, name, _), _| interfaces.contains_key(name));
+        let gone: Vec<Key> = state
+            .active
+            .keys()
+            .filter(|(_, name, _)| !interfaces.contains_key(name))
+            .cloned()
+            .collect();
+        for key in gone {
+            let value = state.active.remove(&key).unwrap().value;
+            alerts.push((key, Transition::Cleared, value));
+        }
+        state
+            .learned
+            .retain(|(_, name), _| interfaces.contains_key(name));
+
+        for ((index, interface, subject), transition, value) in alerts {
+            let rule = &self.rules[index];
+            let alert = Alert {
+                rule: rule.name(),
+                interface: interface.clone(),
+                subject: subject.clone(),
+                state: match transition {
+                    Transition::Raised => AlertState::Raised,
+                    Transition::Cleared => AlertState::Cleared,
+                },
+                value,
+                threshold: rule.thresholds().map(|(above, below)| match transition {
+                    Transition::Raised => above,
+                    Transition::Cleared => below,
+                }),
+                timestamp,
+            };
+            match alert.state {
+                AlertState::Raised => warn!("Alert: {}", alert.message()),
+                AlertState::Cleared => info!("Alert: {}", alert.message()),
+            }
+            let key = (index, interface, subject);
+            // new MAC addresses are one-off events and never clear
+            let one_off = matches!(rule.condition, Condition::NewMac { .. });
+            match transition {
+                Transition::Raised if !one_off => {
+                    state.active.insert(key, alert.clone());
+                }
+                Transition::Raised => {}
+                Transition::Cleared => {
+                    state.active.remove(&key);
+                }
+            }
+            state.history.push_back(alert.clone());
+            if state.history.len() > MAX_HISTORY {
+                state.history.pop_front();
+            }
+            if let Err(e) = self.tx.clone().try_send(alert) {
+                if e.is_full() {
+                    warn!("Alert sinks are behind, notification dropped");
+                }
+            }
+        }
+    }
+
+    /// Delivers the notifications still queued and takes no more, on
+    /// shutdown.
+    pub async fn finish(&self) {
+        self.tx.clone().close_channel();
+        let _ = self.done.clone().await;
+    }
+
+    /// The raised alerts and the history after `since`.
+    pub fn to_json(&self, since: Option<u64>) -> Value {
+        let state = self.state.lock().unwrap();
+        let active: Vec<&Alert> = state.active.values().collect();
+        let history: Vec<&Alert> = state
+            .history
+            .iter()
+            .filter(|alert| match since {
+                Some(since) => alert.timestamp > since,
+                None => true,
+            })
+            .collect();
+        json!({
+            "active": active,
+            "history": history,
+        })
+    }
+}
@@ -0,0 +1,135 @@
+use pnet::util::MacAddr;
+use serde::Deserialize;
+
+/// Share of the raise threshold below which an alert clears, unless the
+/// rule sets its own.
+const DEFAULT_CLEAR_RATIO: f64 = 0.9;
+
+fn default_learn() -> u64 {
+    60
+}
+
+/// One entry of the `--alert-rules` file.
+#[derive(Deserialize)]
+pub struct Rule {
+    /// Shown in notifications, the kind of the rule when missing.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The interface the rule watches, every interface when missing.
+    #[serde(default)]
+    pub interface: Option<String>,
+    #[serde(flatten)]
+    pub condition: Condition,
+    /// Seconds the condition has to hold before the alert is raised.
+    #[serde(default, rename = "for")]
+    pub raise_after: u32,
+    /// Seconds the condition has to be gone before the alert clears, the
+    /// same as `for` when missing.
+    #[serde(default)]
+    pub clear_after: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// Bytes per second of the interface.
+    InterfaceRate { above: f64, below: Option<f64> },
+    /// Percentage of the interface's bytes a single IP address sends or
+    /// receives.
+    HostShare { above: f64, below: Option<f64> },
+    /// A source MAC address the interface has not seen before. Addresses
+    /// are learned silently for `learn` seconds after the interface first
+    /// shows up, besides the `known` ones.
+    NewMac {
+        #[serde(default)]
+        known: Vec<MacAddr>,
+        #[serde(default = "default_learn")]
+        learn: u64,
+    },
+    /// The interface stopped listening, on request or because its capture
+    /// failed.
+    CaptureStopped,
+}
+
+impl Rule {
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match self.condition {
+            Condition::InterfaceRate { .. } => "interface_rate",
+            Condition::HostShare { .. } => "host_share",
+            Condition::NewMac { .. } => "new_mac",
+            Condition::CaptureStopped => "capture_stopped",
+        }
+        .to_owned()
+    }
+
+    pub fn applies_to(&self, interface: &str) -> bool {
+        match &self.interface {
+            Some(name) => name == interface,
+            None => true,
+        }
+    }
+
+    /// The raise and the clear threshold of rules comparing a value.
+    pub fn thresholds(&self) -> Option<(f64, f64)> {
+        match self.condition {
+            Condition::InterfaceRate { above, below } | Condition::HostShare { above, below } => {
+                Some((above, below.unwrap_or(above * DEFAULT_CLEAR_RATIO)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Raised,
+    Cleared,
+}
+
+/// Hysteresis of one alert: it takes `raise_after` ticks beyond the raise
+/// threshold to raise it and `clear_after` ticks back below the clear
+/// threshold to clear it, values in between change nothing.
+#[derive(Default)]
+pub struct Track {
+    pub raised: bool,
+    ticks: u32,
+}
+
+impl Track {
+    pub fn feed(
+        &mut self,
+        high: bool,
+        low: bool,
+        raise_after: u32,
+        clear_after: u32,
+    ) -> Option<Transition> {
+        let (towards, after) = if self.raised {
+            (low, clear_after)
+        } else {
+            (high, raise_after)
+        };
+        if !towards {
+            self.ticks = 0;
+            return None;
+        }
+        self.ticks += 1;
+        if self.ticks < after.max(1) {
+            return None;
+        }
+        self.ticks = 0;
+        self.raised = !self.raised;
+        Some(if self.raised {
+            Transition::Raised
+        } else {
+            Transition::Cleared
+        })
+    }
+
+    /// Nothing worth keeping the track for.
+    pub fn is_idle(&self) -> bool {
+        !self.raised && self.ticks == 0
+    }
+}
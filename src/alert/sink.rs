@@ -0,0 +1,194 @@
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use http_body_util::Full;
+use hyper::rt::{Read, Write};
+use hyper::{header, Request, Uri};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_native_tls::native_tls;
+use tracing::warn;
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{Alert, AlertState};
+
+/// Longest time one notification may take, a sink which hangs must not
+/// hold up the others.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Facility `daemon`.
+const SYSLOG_FACILITY: u8 = 3;
+const SYSLOG_WARNING: u8 = 4;
+const SYSLOG_NOTICE: u8 = 5;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+pub enum SyslogTarget {
+    Udp(SocketAddr),
+    /// A local datagram socket such as `/dev/log`.
+    Unix(PathBuf),
+}
+
+/// An SMTP relay that accepts mail without authentication, usually the
+/// local MTA.
+pub struct Smtp {
+    pub relay: SocketAddr,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Sinks {
+    pub webhooks: Vec<Uri>,
+    pub syslog: Option<SyslogTarget>,
+    pub smtp: Option<Smtp>,
+}
+
+pub async fn run(sinks: Sinks, mut rx: mpsc::Receiver<Alert>) {
+    while let Some(alert) = rx.next().await {
+        for uri in &sinks.webhooks {
+            let res = tokio::time::timeout(DELIVERY_TIMEOUT, webhook(uri, &alert)).await;
+            report(&format!("webhook {}", uri), res);
+        }
+        if let Some(target) = &sinks.syslog {
+            let res = tokio::time::timeout(DELIVERY_TIMEOUT, syslog(target, &alert)).await;
+            report("syslog", res);
+        }
+        if let Some(smtp) = &sinks.smtp {
+            let res = tokio::time::timeout(DELIVERY_TIMEOUT, mail(smtp, &alert)).await;
+            report(&format!("mail relay {}", smtp.relay), res);
+        }
+    }
+}
+
+fn report(sink: &str, res: std::result::Result<Result<()>, tokio::time::error::Elapsed>) {
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!(sink, error = ?e, "Alert notification failed"),
+        Err(_) => warn!(sink, "Alert notification timed out"),
+    }
+}
+
+/// POST the alert as JSON.
+async fn webhook(uri: &Uri, alert: &Alert) -> Result<()> {
+    let host = uri.host().ok_or("webhook URL without host")?;
+    let https = uri.scheme_str() == Some("https");
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let authority = match uri.port_u16() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    };
+    let request = Request::post(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(header::HOST, authority)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(serde_json::to_vec(alert)?)))?;
+    let stream = TcpStream::connect((host, port)).await?;
+    if https {
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        let stream = connector.connect(host, stream).await?;
+        post(TokioIo::new(stream), request).await
+    } else {
+        post(TokioIo::new(stream), request).await
+    }
+}
+
+async fn post<T>(io: T, request: Request<Full<Bytes>>) -> Result<()>
+where
+    T: Read + Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(io).await?;
+    tokio::spawn(connection);
+    let response = sender.send_request(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()).into());
+    }
+    Ok(())
+}
+
+async fn syslog(target: &SyslogTarget, alert: &Alert) -> Result<()> {
+    let severity = match alert.state {
+        AlertState::Raised => SYSLOG_WARNING,
+        AlertState::Cleared => SYSLOG_NOTICE,
+    };
+    // the BSD format, understood by every syslog daemon
+    let message = format!(
+        "<{}>network_view[{}]: {}",
+        SYSLOG_FACILITY * 8 + severity,
+        std::process::id(),
+        alert.message()
+    );
+    match target {
+        SyslogTarget::Udp(address) => {
+            let bind: SocketAddr = match address {
+                SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.send_to(message.as_bytes(), address).await?;
+        }
+        #[cfg(unix)]
+        SyslogTarget::Unix(path) => {
+            let socket = tokio::net::UnixDatagram::unbound()?;
+            socket.send_to(message.as_bytes(), path).await?;
+        }
+        #[cfg(not(unix))]
+        SyslogTarget::Unix(_) => return Err("unix sockets are unavailable".into()),
+    }
+    Ok(())
+}
+
+async fn mail(smtp: &Smtp, alert: &Alert) -> Result<()> {
+    let stream = TcpStream::connect(smtp.relay).await?;
+    let mut stream = BufReader::new(stream);
+    reply(&mut stream, 220).await?;
+    command(&mut stream, "EHLO network_view", 250).await?;
+    command(&mut stream, &format!("MAIL FROM:<{}>", smtp.from), 250).await?;
+    for to in &smtp.to {
+        command(&mut stream, &format!("RCPT TO:<{}>", to), 250).await?;
+    }
+    command(&mut stream, "DATA", 354).await?;
+    let mut data = format!(
+        "From: {}\r\nTo: {}\r\nSubject: [network_view] {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        smtp.from,
+        smtp.to.join(", "),
+        alert.message()
+    );
+    data += &serde_json::to_string_pretty(alert)?.replace('\n', "\r\n");
+    // lines starting with a dot are escaped, a lone dot ends the message
+    data = data.replace("\r\n.", "\r\n..");
+    data += "\r\n.";
+    command(&mut stream, &data, 250).await?;
+    command(&mut stream, "QUIT", 221).await?;
+    Ok(())
+}
+
+async fn command(stream: &mut BufReader<TcpStream>, line: &str, expect: u16) -> Result<()> {
+    stream.write_all(line.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    reply(stream, expect).await
+}
+
+/// Read a possibly multiline reply and check its code.
+async fn reply<R: AsyncBufReadExt + Unpin>(reader: &mut R, expect: u16) -> Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err("connection closed by the mail relay".into());
+        }
+        let code: u16 = line
+            .get(..3)
+            .and_then(|c| c.parse().ok())
+            .ok_or("malformed reply")?;
+        if code != expect {
+            return Err(format!("mail relay replied {}", line.trim_end()).into());
+        }
+        // "250-" continues, "250 " ends the reply
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use pnet::datalink::NetworkInterface;
+
+use std::io;
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{fence, Ordering};
+
+use super::{CaptureSource, FrameInfo, LinkType, READ_TIMEOUT};
+use crate::privilege;
+
+const TPACKET_V3: libc::c_int = 2;
+const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_STATISTICS: libc::c_int = 6;
+const PACKET_VERSION: libc::c_int = 10;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const VLAN_TAG_LEN: usize = 4;
+const PACKET_MR_PROMISC: u16 = 1;
+
+const BLOCK_SIZE: u32 = 1 << 20;
+const BLOCK_COUNT: u32 = 16;
+/// TPACKET_V3 packs frames of any length into a block, this only has to
+/// divide the block size.
+const FRAME_SIZE: u32 = 2048;
+/// Hand a partially filled block to user space after this many milliseconds.
+const BLOCK_TIMEOUT: u32 = 100;
+
+#[repr(C)]
+struct TpacketReq3 {
+    tp_block_size: u32,
+    tp_block_nr: u32,
+    tp_frame_size: u32,
+    tp_frame_nr: u32,
+    tp_retire_blk_tov: u32,
+    tp_sizeof_priv: u32,
+    tp_feature_req_word: u32,
+}
+
+#[repr(C)]
+struct PacketMreq {
+    mr_ifindex: libc::c_int,
+    mr_type: u16,
+    mr_alen: u16,
+    mr_address: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TpacketStatsV3 {
+    tp_packets: u32,
+    tp_drops: u32,
+    tp_freeze_q_cnt: u32,
+}
+
+#[repr(C)]
+struct TpacketBlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: [u32; 2],
+    ts_last_pkt: [u32; 2],
+}
+
+#[repr(C)]
+struct Tpacket3Hdr {
+    tp_next_offset: u32,
+    tp_sec: u32,
+    tp_nsec: u32,
+    tp_snaplen: u32,
+    tp_len: u32,
+    tp_status: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    tp_rxhash: u32,
+    tp_vlan_tci: u32,
+    tp_vlan_tpid: u16,
+    tp_padding: u16,
+}
+
+/// AF_PACKET socket reading from a TPACKET_V3 ring shared with the kernel.
+///
+/// Frames are borrowed straight out of the ring, a block is returned to the
+/// kernel once every frame in it has been handed out. The interface is put
+/// into promiscuous mode for as long as the socket is open, like the other
+/// live backends do.
+pub struct AfPacketSource {
+    fd: libc::c_int,
+    ring: *mut u8,
+    block: u32,
+    remaining: u32,
+    offset: usize,
+    held: bool,
+    dropped: u64,
+    link_type: LinkType,
+}
+
+// The ring is only ever touched by the thread owning the source.
+unsafe impl Send for AfPacketSource {}
+
+impl AfPacketSource {
+    pub fn open(interface: &NetworkInterface) -> io::Result<Self> {
+        // frames are only received once bound, for this protocol
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        // from the capture helper when the privileges are separated
+        let fd = privilege::packet_socket()?.into_raw_fd();
+        let mut source = Self {
+            fd,
+            ring: std::ptr::null_mut(),
+            block: 0,
+            remaining: 0,
+            offset: 0,
+            held: false,
+            dropped: 0,
+            link_type: LinkType::of_interface(&interface.name),
+        };
+
+        setsockopt(fd, PACKET_VERSION, &TPACKET_V3)?;
+        let req = TpacketReq3 {
+            tp_block_size: BLOCK_SIZE,
+            tp_block_nr: BLOCK_COUNT,
+            tp_frame_size: FRAME_SIZE,
+            tp_frame_nr: BLOCK_SIZE / FRAME_SIZE * BLOCK_COUNT,
+            tp_retire_blk_tov: BLOCK_TIMEOUT,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        setsockopt(fd, PACKET_RX_RING, &req)?;
+
+        let ring = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                (BLOCK_SIZE * BLOCK_COUNT) as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        source.ring = ring as *mut u8;
+
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = protocol;
+        address.sll_ifindex = interface.index as i32;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let membership = PacketMreq {
+            mr_ifindex: interface.index as libc::c_int,
+            mr_type: PACKET_MR_PROMISC,
+            mr_alen: 0,
+            mr_address: [0; 8],
+        };
+        setsockopt(fd, PACKET_ADD_MEMBERSHIP, &membership)?;
+        Ok(source)
+    }
+
+    fn block_desc(&self, block: u32) -> *mut TpacketBlockDesc {
+        unsafe { self.ring.add((block * BLOCK_SIZE) as usize) as *mut TpacketBlockDesc }
+    }
+
+    fn release(&mut self) {
+        if self.held {
+            let desc = self.block_desc(self.block);
+            fence(Ordering::Release);
+            unsafe { std::ptr::write_volatile(&mut (*desc).block_status, TP_STATUS_KERNEL) };
+            self.held = false;
+            self.block = (self.block + 1) % BLOCK_COUNT;
+        }
+    }
+
+    /// Wait up to `READ_TIMEOUT` for the kernel to hand over the current
+    /// block, returns whether it did.
+    fn acquire(&mut self) -> io::Result<bool> {
+        let desc = self.block_desc(self.block);
+        let status = unsafe { std::ptr::read_volatile(&(*desc).block_status) };
+        if status & TP_STATUS_USER == 0 {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            let timeout = READ_TIMEOUT.as_millis() as libc::c_int;
+            if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            let status = unsafe { std::ptr::read_volatile(&(*desc).block_status) };
+            if status & TP_STATUS_USER == 0 {
+                return Ok(false);
+            }
+        }
+        fence(Ordering::Acquire);
+        unsafe {
+            self.remaining = (*desc).num_pkts;
+            self.offset = (self.block * BLOCK_SIZE + (*desc).offset_to_first_pkt) as usize;
+        }
+        self.held = true;
+        Ok(true)
+    }
+
+    /// Borrow the frame at the cursor and advance past it. Along with the
+    /// captured bytes come the length on the wire, which is larger for frames
+    /// the kernel had to cut short to fit them into a block, and the VLAN tag
+    /// the kernel or the NIC took out of the frame.
+    fn frame(&mut self) -> (&[u8], FrameInfo) {
+        self.remaining -= 1;
+        unsafe {
+            let header = &*(self.ring.add(self.offset) as *const Tpacket3Hdr);
+            let data = self.ring.add(self.offset + header.tp_mac as usize);
+            self.offset += header.tp_next_offset as usize;
+            let vlan = (header.tp_status & TP_STATUS_VLAN_VALID != 0)
+                .then_some(header.tp_vlan_tci as u16 & 0x0fff);
+            let info = FrameInfo {
+                // the tag was on the wire as well
+                len: header.tp_len as usize + vlan.map_or(0, |_| VLAN_TAG_LEN),
+                vlan,
+            };
+            (
+                std::slice::from_raw_parts(data, header.tp_snaplen as usize),
+                info,
+            )
+        }
+    }
+}
+
+impl CaptureSource for AfPacketSource {
+    fn next(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.remaining == 0 {
+            self.release();
+            if !self.acquire()? {
+                return Ok(None);
+            }
+            if self.remaining == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.frame().0))
+    }
+
+    fn next_batch(&mut self, handle: &mut dyn FnMut(&[u8], FrameInfo)) -> io::Result<()> {
+        if self.remaining == 0 {
+            self.release();
+            if !self.acquire()? {
+                return Ok(());
+            }
+        }
+        while self.remaining > 0 {
+            let (frame, info) = self.frame();
+            handle(frame, info);
+        }
+        self.release();
+        Ok(())
+    }
+
+    fn dropped(&mut self) -> Option<u64> {
+        // the kernel resets its counters on every read
+        let mut stats = TpacketStatsV3::default();
+        let mut len = std::mem::size_of::<TpacketStatsV3>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                self.fd,
+                libc::SOL_PACKET,
+                PACKET_STATISTICS,
+                &mut stats as *mut TpacketStatsV3 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return None;
+        }
+        self.dropped += stats.tp_drops as u64;
+        Some(self.dropped)
+    }
+
+    fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+}
+
+impl Drop for AfPacketSource {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                libc::munmap(
+                    self.ring as *mut libc::c_void,
+                    (BLOCK_SIZE * BLOCK_COUNT) as usize,
+                );
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn setsockopt<T>(fd: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_PACKET,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
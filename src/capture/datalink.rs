@@ -0,0 +1,177 @@
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+use pnet::datalink::bpf;
+use pnet::datalink::{self, Channel, DataLinkReceiver, NetworkInterface};
+
+use std::io;
+
+use super::{CaptureSource, LinkType, READ_TIMEOUT};
+
+#[cfg(not(target_os = "linux"))]
+const ETHERNET_HEADER_LEN: usize = 14;
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+const NULL_HEADER_LEN: usize = 4;
+
+pub struct DatalinkSource {
+    rx: Box<dyn DataLinkReceiver>,
+    link_type: LinkType,
+    /// pnet replaces the 4 byte `DLT_NULL` header of BSD loopback and tun
+    /// devices with a zeroed prefix, which is skipped again here.
+    offset: usize,
+}
+
+impl DatalinkSource {
+    pub fn open(interface: &NetworkInterface) -> io::Result<Self> {
+        let config = datalink::Config {
+            read_timeout: Some(READ_TIMEOUT),
+            ..Default::default()
+        };
+        let rx = match datalink::channel(interface, config)? {
+            Channel::Ethernet(_, rx) => rx,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unhandled channel type",
+                ))
+            }
+        };
+        let (link_type, offset) = link_type(interface)?;
+        Ok(Self {
+            rx,
+            link_type,
+            offset,
+        })
+    }
+}
+
+/// The link type of the frames pnet hands over for the interface, and how
+/// many bytes in front of them to skip.
+#[cfg(target_os = "linux")]
+fn link_type(interface: &NetworkInterface) -> io::Result<(LinkType, usize)> {
+    Ok((LinkType::of_interface(&interface.name), 0))
+}
+
+/// Goes by the `DLT_*` of the interface like pnet does: only `DLT_NULL`
+/// frames (loopback, utun, gif) get their address family replaced, by zeros
+/// up to the BPF header alignment. Every other link type is passed on as is.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+fn link_type(interface: &NetworkInterface) -> io::Result<(LinkType, usize)> {
+    let dlt = bpf_dlt(&interface.name)?;
+    if dlt == bpf::DLT_NULL {
+        let prefix = (ETHERNET_HEADER_LEN - NULL_HEADER_LEN)
+            .next_multiple_of(std::mem::align_of::<bpf::bpf_hdr>());
+        return Ok((LinkType::RawIp, prefix));
+    }
+    match LinkType::from_dlt(dlt as i32) {
+        Some(link_type) => Ok((link_type, 0)),
+        None => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported link type {}", dlt),
+        )),
+    }
+}
+
+/// Where pnet doesn't tell the link type, loopback and interfaces without
+/// hardware address are taken to have a `DLT_NULL` header.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+)))]
+fn link_type(interface: &NetworkInterface) -> io::Result<(LinkType, usize)> {
+    if interface.mac.is_none() || interface.is_loopback() {
+        Ok((LinkType::RawIp, ETHERNET_HEADER_LEN))
+    } else {
+        Ok((LinkType::Ethernet, 0))
+    }
+}
+
+/// Asks a BPF device attached to the interface for its link type.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+fn bpf_dlt(name: &str) -> io::Result<libc::c_uint> {
+    use std::os::fd::AsRawFd;
+
+    let device = open_bpf()?;
+    let mut request: bpf::ifreq = unsafe { std::mem::zeroed() };
+    if name.len() >= request.ifr_name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "interface name too long",
+        ));
+    }
+    for (c, b) in request.ifr_name.iter_mut().zip(name.bytes()) {
+        *c = b as libc::c_char;
+    }
+    let fd = device.as_raw_fd();
+    if unsafe { libc::ioctl(fd, bpf::BIOCSETIF, &request) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut dlt: libc::c_uint = 0;
+    if unsafe { libc::ioctl(fd, bpf::BIOCGDLT, &mut dlt) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(dlt)
+}
+
+/// The cloning `/dev/bpf`, or the first free numbered device on systems
+/// without one.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+fn open_bpf() -> io::Result<std::fs::File> {
+    let mut last = io::Error::from(io::ErrorKind::NotFound);
+    let paths =
+        std::iter::once("/dev/bpf".to_owned()).chain((0..256).map(|i| format!("/dev/bpf{}", i)));
+    for path in paths {
+        match std::fs::File::open(&path) {
+            Ok(device) => return Ok(device),
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::EBUSY) =>
+            {
+                last = e
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last)
+}
+
+impl CaptureSource for DatalinkSource {
+    fn next(&mut self) -> io::Result<Option<&[u8]>> {
+        match self.rx.next() {
+            Ok(frame) => Ok(frame.get(self.offset..)),
+            Err(e) => match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+}
@@ -0,0 +1,167 @@
+use pnet::datalink::NetworkInterface;
+
+use std::ffi::{c_char, c_int, c_uint, CStr, CString};
+use std::io;
+
+use super::{CaptureSource, LinkType, READ_TIMEOUT};
+
+const PCAP_ERRBUF_SIZE: usize = 256;
+const SNAPLEN: c_int = 65535;
+
+#[allow(non_camel_case_types)]
+enum pcap_t {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct pcap_pkthdr {
+    ts: libc::timeval,
+    caplen: u32,
+    len: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct bpf_program {
+    bf_len: c_uint,
+    bf_insns: *mut libc::c_void,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_camel_case_types)]
+struct pcap_stat {
+    ps_recv: c_uint,
+    ps_drop: c_uint,
+    ps_ifdrop: c_uint,
+}
+
+#[link(name = "pcap")]
+extern "C" {
+    fn pcap_open_live(
+        device: *const c_char,
+        snaplen: c_int,
+        promisc: c_int,
+        to_ms: c_int,
+        errbuf: *mut c_char,
+    ) -> *mut pcap_t;
+    fn pcap_next_ex(p: *mut pcap_t, header: *mut *mut pcap_pkthdr, data: *mut *const u8)
+        -> c_int;
+    fn pcap_compile(
+        p: *mut pcap_t,
+        program: *mut bpf_program,
+        expression: *const c_char,
+        optimize: c_int,
+        netmask: u32,
+    ) -> c_int;
+    fn pcap_setfilter(p: *mut pcap_t, program: *mut bpf_program) -> c_int;
+    fn pcap_freecode(program: *mut bpf_program);
+    fn pcap_stats(p: *mut pcap_t, stats: *mut pcap_stat) -> c_int;
+    fn pcap_datalink(p: *mut pcap_t) -> c_int;
+    fn pcap_geterr(p: *mut pcap_t) -> *mut c_char;
+    fn pcap_close(p: *mut pcap_t);
+}
+
+/// libpcap handle, with an optional filter compiled into the kernel.
+pub struct PcapSource {
+    handle: *mut pcap_t,
+    link_type: LinkType,
+}
+
+// The handle is only ever used by the thread owning the source.
+unsafe impl Send for PcapSource {}
+
+impl PcapSource {
+    pub fn open(interface: &NetworkInterface, filter: Option<&str>) -> io::Result<Self> {
+        let device = CString::new(interface.name.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+        let handle = unsafe {
+            pcap_open_live(
+                device.as_ptr(),
+                SNAPLEN,
+                1,
+                READ_TIMEOUT.as_millis() as c_int,
+                errbuf.as_mut_ptr(),
+            )
+        };
+        if handle.is_null() {
+            let message = unsafe { CStr::from_ptr(errbuf.as_ptr()) };
+            return Err(io::Error::other(message.to_string_lossy().into_owned()));
+        }
+        let dlt = unsafe { pcap_datalink(handle) };
+        let link_type = match LinkType::from_dlt(dlt) {
+            Some(link_type) => link_type,
+            None => {
+                unsafe { pcap_close(handle) };
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported link type {}", dlt),
+                ));
+            }
+        };
+        let source = Self { handle, link_type };
+        if let Some(filter) = filter {
+            source.set_filter(filter)?;
+        }
+        Ok(source)
+    }
+
+    fn set_filter(&self, filter: &str) -> io::Result<()> {
+        let expression = CString::new(filter)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut program = bpf_program {
+            bf_len: 0,
+            bf_insns: std::ptr::null_mut(),
+        };
+        unsafe {
+            if pcap_compile(self.handle, &mut program, expression.as_ptr(), 1, 0) != 0 {
+                return Err(self.error(io::ErrorKind::InvalidInput));
+            }
+            let res = pcap_setfilter(self.handle, &mut program);
+            pcap_freecode(&mut program);
+            if res != 0 {
+                return Err(self.error(io::ErrorKind::Other));
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, kind: io::ErrorKind) -> io::Error {
+        let message = unsafe { CStr::from_ptr(pcap_geterr(self.handle)) };
+        io::Error::new(kind, message.to_string_lossy().into_owned())
+    }
+}
+
+impl CaptureSource for PcapSource {
+    fn next(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut header: *mut pcap_pkthdr = std::ptr::null_mut();
+        let mut data: *const u8 = std::ptr::null();
+        match unsafe { pcap_next_ex(self.handle, &mut header, &mut data) } {
+            1 => {
+                let len = unsafe { (*header).caplen } as usize;
+                Ok(Some(unsafe { std::slice::from_raw_parts(data, len) }))
+            }
+            // read timeout expired without a packet
+            0 => Ok(None),
+            _ => Err(self.error(io::ErrorKind::Other)),
+        }
+    }
+
+    fn dropped(&mut self) -> Option<u64> {
+        let mut stats = pcap_stat::default();
+        if unsafe { pcap_stats(self.handle, &mut stats) } != 0 {
+            return None;
+        }
+        Some(stats.ps_drop as u64 + stats.ps_ifdrop as u64)
+    }
+
+    fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+}
+
+impl Drop for PcapSource {
+    fn drop(&mut self) {
+        unsafe { pcap_close(self.handle) };
+    }
+}
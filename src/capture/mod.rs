@@ -0,0 +1,243 @@
+#[cfg(target_os = "linux")]
+mod af_packet;
+mod datalink;
+#[cfg(feature = "libpcap")]
+mod libpcap;
+mod pcap_file;
+
+use pnet::datalink::NetworkInterface;
+use serde::{Deserialize, Serialize};
+
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Longest time a backend blocks before returning without a frame, and so
+/// the longest a capture takes to notice it was stopped.
+pub const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A source of raw link layer frames.
+///
+/// Implementations block the calling thread, so they are driven from
+/// `spawn_blocking` and never from the async runtime directly.
+pub trait CaptureSource: Send {
+    /// Wait for the next frame, `None` once `READ_TIMEOUT` expires without
+    /// one. An error ends the capture.
+    fn next(&mut self) -> io::Result<Option<&[u8]>>;
+
+    /// Hand every frame that is ready to `handle` along with what the
+    /// backend knows about it, waiting at most `READ_TIMEOUT` for the first
+    /// one. Backends which receive frames in blocks override this to process
+    /// a whole block per call.
+    fn next_batch(&mut self, handle: &mut dyn FnMut(&[u8], FrameInfo)) -> io::Result<()> {
+        if let Some(frame) = self.next()? {
+            handle(frame, FrameInfo::of(frame));
+        }
+        Ok(())
+    }
+
+    /// Frames the kernel dropped since the source was opened, `None` when
+    /// the backend has no way to tell.
+    fn dropped(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// The header every frame of this source starts with.
+    fn link_type(&self) -> LinkType;
+}
+
+/// What a backend knows about a frame beyond its captured bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Length on the wire, larger than the captured bytes when the frame was
+    /// cut short.
+    pub len: usize,
+    /// VLAN ID of the tag the kernel or the NIC removed from the frame.
+    pub vlan: Option<u16>,
+}
+
+impl FrameInfo {
+    /// A frame captured whole and with its tags in place.
+    pub fn of(frame: &[u8]) -> Self {
+        Self {
+            len: frame.len(),
+            vlan: None,
+        }
+    }
+}
+
+/// Link layer header in front of the network layer packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    /// No link layer header at all: tun, WireGuard, PPP on Linux.
+    RawIp,
+    /// Linux cooked capture (`DLT_LINUX_SLL`).
+    LinuxSll,
+    /// Linux cooked capture v2 (`DLT_LINUX_SLL2`).
+    LinuxSll2,
+    /// BSD loopback: a 4 byte address family (`DLT_NULL` and `DLT_LOOP`).
+    Null,
+}
+
+impl LinkType {
+    /// From a libpcap `DLT_*`/`LINKTYPE_*` value.
+    pub fn from_dlt(dlt: i32) -> Option<Self> {
+        match dlt {
+            0 | 108 => Some(LinkType::Null),
+            1 => Some(LinkType::Ethernet),
+            12 | 14 | 101 | 228 | 229 => Some(LinkType::RawIp),
+            113 => Some(LinkType::LinuxSll),
+            276 => Some(LinkType::LinuxSll2),
+            _ => None,
+        }
+    }
+
+    /// What a Linux packet socket delivers for an interface, going by the
+    /// `ARPHRD_*` hardware type in `/sys/class/net/<name>/type`.
+    #[cfg(target_os = "linux")]
+    pub fn of_interface(name: &str) -> Self {
+        let hatype = std::fs::read_to_string(format!("/sys/class/net/{}/type", name))
+            .ok()
+            .and_then(|s| s.trim().parse::<u16>().ok());
+        match hatype {
+            // ARPHRD_PPP, ARPHRD_TUNNEL, ARPHRD_TUNNEL6, ARPHRD_SIT,
+            // ARPHRD_IPGRE, ARPHRD_IP6GRE, ARPHRD_RAWIP, ARPHRD_NONE
+            Some(512 | 768 | 769 | 776 | 778 | 823 | 519 | 65534) => LinkType::RawIp,
+            // ARPHRD_ETHER, ARPHRD_LOOPBACK and anything unknown
+            _ => LinkType::Ethernet,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// `pnet::datalink` channel, available on every platform pnet supports.
+    #[default]
+    Datalink,
+    /// libpcap, the only backend that supports kernel BPF filters.
+    Pcap,
+    /// Linux AF_PACKET socket with a TPACKET_V3 mmap ring.
+    AfPacket,
+    /// Replay of a pcap file instead of a live interface.
+    PcapFile,
+    /// No capture at all: the history is filled from the kernel's
+    /// interface counters, which needs no privileges but only gives totals.
+    Counters,
+}
+
+impl Backend {
+    /// Whether the backend opens a live capture, which takes privileges.
+    pub fn is_live(self) -> bool {
+        !matches!(self, Backend::PcapFile | Backend::Counters)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned())).map_err(|_| {
+            format!(
+                "unknown capture backend {:?} (expected datalink, pcap, af_packet, pcap_file or counters)",
+                s
+            )
+        })
+    }
+}
+
+/// Per interface capture settings, sent along with `listen_interfaces`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CaptureConfig {
+    /// Falls back to the server default (`--capture-backend`) when missing.
+    #[serde(default)]
+    pub backend: Option<Backend>,
+    /// pcap filter expression, compiled to a kernel BPF program.
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Path of the file to replay with the `pcap_file` backend.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Replay the file at its recorded speed instead of as fast as possible.
+    #[serde(default)]
+    pub realtime: bool,
+    /// Account the packets inside tunnels rather than the tunnel packets.
+    #[serde(default)]
+    pub decapsulate: bool,
+    /// Look into TLS, QUIC and HTTP requests for the server name of flows.
+    #[serde(default)]
+    pub inspect: bool,
+    /// Follow the state of TCP connections.
+    #[serde(default)]
+    pub track_flows: bool,
+    /// Learn traffic baselines and report deviations from them.
+    #[serde(default)]
+    pub anomalies: bool,
+    /// Listen again whenever the interface comes back after it went away
+    /// or down, until listening is stopped on request.
+    #[serde(default)]
+    pub persistent: bool,
+}
+
+/// Whether this process may open live captures. On Linux the packet socket
+/// captures start with is opened and closed again, on a blocking thread as
+/// the capture helper may be busy; elsewhere it only shows when listening.
+pub async fn permitted() -> bool {
+    #[cfg(target_os = "linux")]
+    return tokio::task::spawn_blocking(|| crate::privilege::probe_packet_socket().is_ok())
+        .await
+        .unwrap_or(false);
+    #[cfg(not(target_os = "linux"))]
+    true
+}
+
+pub fn open(
+    interface: Option<&NetworkInterface>,
+    config: &CaptureConfig,
+    default_backend: Backend,
+) -> io::Result<Box<dyn CaptureSource>> {
+    let backend = config.backend.unwrap_or(default_backend);
+    if config.filter.is_some() && backend != Backend::Pcap {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "capture filter requires the pcap backend",
+        ));
+    }
+    let interface =
+        || interface.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "interface not found"));
+    match backend {
+        Backend::Datalink => Ok(Box::new(datalink::DatalinkSource::open(interface()?)?)),
+        #[cfg(feature = "libpcap")]
+        Backend::Pcap => Ok(Box::new(libpcap::PcapSource::open(
+            interface()?,
+            config.filter.as_deref(),
+        )?)),
+        #[cfg(not(feature = "libpcap"))]
+        Backend::Pcap => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pcap backend unavailable. Please rebuild project with `libpcap` feature enable",
+        )),
+        #[cfg(target_os = "linux")]
+        Backend::AfPacket => Ok(Box::new(af_packet::AfPacketSource::open(interface()?)?)),
+        #[cfg(not(target_os = "linux"))]
+        Backend::AfPacket => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "af_packet backend is only available on linux",
+        )),
+        Backend::PcapFile => match &config.file {
+            Some(path) => Ok(Box::new(pcap_file::PcapFileSource::open(
+                path,
+                config.realtime,
+            )?)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pcap_file backend requires a file",
+            )),
+        },
+        Backend::Counters => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "counters backend polls the kernel and opens no capture",
+        )),
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::time::{Duration, Instant};
+
+use super::{CaptureSource, FrameInfo, LinkType, READ_TIMEOUT};
+
+const MAGIC_MICROSECOND: u32 = 0xa1b2c3d4;
+const MAGIC_NANOSECOND: u32 = 0xa1b23c4d;
+/// Largest record accepted whatever the file header claims, the snapshot
+/// length tcpdump uses by default.
+const MAX_CAPTURED: usize = 262144;
+
+/// Reads frames from a classic libpcap file (not pcapng).
+pub struct PcapFileSource {
+    reader: BufReader<File>,
+    big_endian: bool,
+    nanosecond: bool,
+    realtime: Option<(Instant, Duration)>,
+    pacing: bool,
+    /// The record header of a frame which is not due yet.
+    pending: Option<[u8; 16]>,
+    /// Longest record the file may hold, from its header.
+    snaplen: usize,
+    buffer: Vec<u8>,
+    link_type: LinkType,
+}
+
+impl PcapFileSource {
+    pub fn open(path: &str, realtime: bool) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (big_endian, nanosecond) = match magic {
+            MAGIC_MICROSECOND => (false, false),
+            MAGIC_NANOSECOND => (false, true),
+            _ => match magic.swap_bytes() {
+                MAGIC_MICROSECOND => (true, false),
+                MAGIC_NANOSECOND => (true, true),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "not a pcap file",
+                    ))
+                }
+            },
+        };
+        let mut source = Self {
+            reader,
+            big_endian,
+            nanosecond,
+            realtime: None,
+            pacing: realtime,
+            pending: None,
+            snaplen: MAX_CAPTURED,
+            buffer: Vec::with_capacity(65536),
+            link_type: LinkType::Ethernet,
+        };
+        // writers which leave the snapshot length out store 0
+        let snaplen = source.u32(&header[16..20]) as usize;
+        if snaplen > 0 {
+            source.snaplen = snaplen.min(MAX_CAPTURED);
+        }
+        let dlt = source.u32(&header[20..24]) & 0x0fff_ffff;
+        source.link_type = LinkType::from_dlt(dlt as i32).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported link type {}", dlt),
+            )
+        })?;
+        Ok(source)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Sleep until the frame recorded at `timestamp` is due, but no longer
+    /// than `READ_TIMEOUT` so the capture can still be stopped across gaps in
+    /// the recording. Whether the frame is due by then.
+    fn wait(&mut self, timestamp: Duration) -> bool {
+        match self.realtime {
+            Some((start, first)) => {
+                let due = timestamp.saturating_sub(first);
+                let elapsed = start.elapsed();
+                if due <= elapsed {
+                    return true;
+                }
+                std::thread::sleep((due - elapsed).min(READ_TIMEOUT));
+                due - elapsed <= READ_TIMEOUT
+            }
+            None => {
+                self.realtime = Some((Instant::now(), timestamp));
+                true
+            }
+        }
+    }
+
+    /// Reads the next frame into the buffer, returns its length on the wire.
+    fn read(&mut self) -> io::Result<Option<usize>> {
+        let header = match self.pending.take() {
+            Some(header) => header,
+            None => {
+                let mut header = [0u8; 16];
+                self.reader.read_exact(&mut header)?;
+                header
+            }
+        };
+        let seconds = self.u32(&header[0..4]);
+        let fraction = self.u32(&header[4..8]);
+        let captured = self.u32(&header[8..12]) as usize;
+        let original = self.u32(&header[12..16]) as usize;
+        // a corrupt length would have the buffer grow to gigabytes
+        if captured > self.snaplen {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record of {} bytes exceeds the snapshot length", captured),
+            ));
+        }
+        if self.pacing {
+            let fraction = if self.nanosecond {
+                fraction
+            } else {
+                fraction.saturating_mul(1000)
+            };
+            if !self.wait(Duration::new(seconds as u64, fraction)) {
+                self.pending = Some(header);
+                return Ok(None);
+            }
+        }
+        self.buffer.resize(captured, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        // writers which do not truncate may leave the original length out
+        Ok(Some(original.max(captured)))
+    }
+}
+
+impl CaptureSource for PcapFileSource {
+    fn next(&mut self) -> io::Result<Option<&[u8]>> {
+        Ok(self.read()?.map(|_| &self.buffer[..]))
+    }
+
+    /// Frames cut short by the snapshot length are counted at their length
+    /// on the wire, as live captures count them.
+    fn next_batch(&mut self, handle: &mut dyn FnMut(&[u8], FrameInfo)) -> io::Result<()> {
+        if let Some(len) = self.read()? {
+            handle(&self.buffer, FrameInfo { len, vlan: None });
+        }
+        Ok(())
+    }
+
+    fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+}
@@ -0,0 +1,164 @@
+//! sFlow v5, NetFlow v5/v9 and IPFIX collector, an alternative to capturing
+//! for devices which cannot run this binary but export what they forward.
+
+use futures::lock::Mutex;
+use pnet::util::MacAddr;
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::statistics::{InterfaceStatistics, RemoteInterfaces};
+
+mod netflow;
+mod sflow;
+
+use netflow::Templates;
+
+/// Largest datagram accepted, anything above the usual MTU is a jumbo frame.
+const MAX_DATAGRAM: usize = 65535;
+/// How often the collected totals are handed to the ticker.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectorProtocol {
+    Sflow,
+    Netflow5,
+    Netflow9,
+    Ipfix,
+}
+
+/// Where the traffic of a collected interface comes from.
+#[derive(Clone, Copy, Serialize)]
+pub struct RemoteSource {
+    /// sFlow agent address, or the sender of NetFlow and IPFIX datagrams.
+    pub exporter: IpAddr,
+    pub if_index: u32,
+    pub protocol: CollectorProtocol,
+}
+
+impl RemoteSource {
+    /// The name of the interface in responses, `exporter/ifIndex`.
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.exporter, self.if_index)
+    }
+}
+
+/// A flow record, its counters already scaled by the sampling rate.
+pub struct FlowSample {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: u8,
+    pub source_mac: Option<MacAddr>,
+    pub destination_mac: Option<MacAddr>,
+    pub vlan: Option<u16>,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// What one datagram carried.
+pub enum Sample<'a> {
+    /// The leading bytes of a sampled frame, sFlow's raw packet header.
+    Frame {
+        frame: &'a [u8],
+        /// The frame starts with the IP header rather than Ethernet.
+        raw_ip: bool,
+        packets: u64,
+        bytes: u64,
+    },
+    Flow(FlowSample),
+}
+
+pub async fn run(
+    address: SocketAddr,
+    start_time: Instant,
+    map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>,
+) {
+    let socket = match UdpSocket::bind(address).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!(error = ?e, "Flow collector unavailable");
+            return;
+        }
+    };
+    info!("collect flows");
+
+    let mut interfaces = RemoteInterfaces::new(start_time, map);
+    let mut templates = Templates::default();
+    let mut malformed = HashSet::new();
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buffer) => {
+                let (len, sender) = match res {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!(error = ?e, "Flow collector receive error");
+                        continue;
+                    }
+                };
+                let datagram = &buffer[..len];
+                let mut add = |source: RemoteSource, sample: Sample| interfaces.add(source, sample);
+                let parsed = match datagram {
+                    // sFlow has a 32 bit version, NetFlow and IPFIX a 16 bit one
+                    [0, 0, 0, 5, ..] => sflow::parse(datagram, &mut add),
+                    [0, 5, ..] => netflow::parse_v5(sender.ip(), datagram, &mut add),
+                    [0, 9, ..] | [0, 10, ..] => {
+                        templates.parse(sender.ip(), datagram, &mut add)
+                    }
+                    _ => None,
+                };
+                // once per exporter, a misconfigured one sends nothing else
+                if parsed.is_none() && malformed.insert(sender.ip()) {
+                    warn!(%sender, "Flow collector ignores malformed datagrams");
+                }
+            }
+            _ = interval.tick() => interfaces.flush().await,
+        }
+    }
+}
+
+/// Big endian reads off the front of a datagram, `None` once it runs out.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn ipv4(&mut self) -> Option<IpAddr> {
+        let b: [u8; 4] = self.take(4)?.try_into().ok()?;
+        Some(IpAddr::from(b))
+    }
+
+    fn ipv6(&mut self) -> Option<IpAddr> {
+        let b: [u8; 16] = self.take(16)?.try_into().ok()?;
+        Some(IpAddr::from(b))
+    }
+}
@@ -0,0 +1,431 @@
+use pnet::util::MacAddr;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::{CollectorProtocol, FlowSample, Reader, RemoteSource, Sample};
+
+const NETFLOW5_RECORD_LEN: usize = 48;
+const NETFLOW9_VERSION: u16 = 9;
+const NETFLOW9_TEMPLATE_SET: u16 = 0;
+const NETFLOW9_OPTIONS_TEMPLATE_SET: u16 = 1;
+const IPFIX_TEMPLATE_SET: u16 = 2;
+const IPFIX_OPTIONS_TEMPLATE_SET: u16 = 3;
+const FIRST_DATA_SET: u16 = 256;
+const IPFIX_HEADER_LEN: usize = 16;
+const ENTERPRISE_BIT: u16 = 0x8000;
+const VARIABLE_LENGTH: u16 = 65535;
+/// Templates kept per collector, exporters churning through template ids
+/// would otherwise grow the table without limit.
+const MAX_TEMPLATES: usize = 4096;
+
+// NetFlow v9 field types, the IPFIX information elements share the numbers
+const IN_BYTES: u16 = 1;
+const IN_PKTS: u16 = 2;
+const PROTOCOL: u16 = 4;
+const IPV4_SRC_ADDR: u16 = 8;
+const INPUT_SNMP: u16 = 10;
+const IPV4_DST_ADDR: u16 = 12;
+const IPV6_SRC_ADDR: u16 = 27;
+const IPV6_DST_ADDR: u16 = 28;
+const SAMPLING_INTERVAL: u16 = 34;
+const FLOW_SAMPLER_ID: u16 = 48;
+const FLOW_SAMPLER_RANDOM_INTERVAL: u16 = 50;
+const IN_SRC_MAC: u16 = 56;
+const SRC_VLAN: u16 = 58;
+const IN_DST_MAC: u16 = 80;
+const SELECTOR_ID: u16 = 302;
+const SAMPLING_PACKET_INTERVAL: u16 = 305;
+const SAMPLING_PACKET_SPACE: u16 = 306;
+/// Stands in for enterprise specific elements and NetFlow v9 scope types,
+/// which would otherwise be mistaken for the fields above.
+const UNKNOWN: u16 = 0;
+
+/// Account the records of a NetFlow v5 datagram.
+pub fn parse_v5(
+    exporter: IpAddr,
+    datagram: &[u8],
+    add: &mut dyn FnMut(RemoteSource, Sample),
+) -> Option<()> {
+    let mut r = Reader(datagram);
+    r.u16()?; // version
+    let count = r.u16()?;
+    // uptime, unix seconds and nanoseconds, sequence, engine
+    r.take(18)?;
+    // the sampling interval, the top two bits are the sampling mode
+    let rate = (r.u16()? & 0x3fff).max(1) as u64;
+    for _ in 0..count {
+        let mut record = Reader(r.take(NETFLOW5_RECORD_LEN)?);
+        let source = record.ipv4()?;
+        let destination = record.ipv4()?;
+        record.take(4)?; // next hop
+        let if_index = record.u16()? as u32;
+        record.take(2)?; // output interface
+        let packets = record.u32()? as u64;
+        let bytes = record.u32()? as u64;
+        record.take(14)?; // first, last, ports, padding, TCP flags
+        let protocol = record.u8()?;
+        let remote = RemoteSource {
+            exporter,
+            if_index,
+            protocol: CollectorProtocol::Netflow5,
+        };
+        add(
+            remote,
+            Sample::Flow(FlowSample {
+                source,
+                destination,
+                protocol,
+                source_mac: None,
+                destination_mac: None,
+                vlan: None,
+                packets: packets * rate,
+                bytes: bytes * rate,
+            }),
+        );
+    }
+    Some(())
+}
+
+/// Template ids are scoped to the exporter, the version and the source id
+/// (NetFlow v9) or observation domain (IPFIX).
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct Domain {
+    exporter: IpAddr,
+    version: u16,
+    id: u32,
+}
+
+struct Field {
+    id: u16,
+    len: u16,
+}
+
+struct Template {
+    fields: Vec<Field>,
+    /// Options templates describe the exporter, sampler settings among
+    /// others, rather than flows.
+    options: bool,
+}
+
+/// The fields of a data record this collector makes use of.
+#[derive(Default)]
+struct Record {
+    source: Option<IpAddr>,
+    destination: Option<IpAddr>,
+    protocol: u8,
+    packets: u64,
+    bytes: u64,
+    if_index: u32,
+    source_mac: Option<MacAddr>,
+    destination_mac: Option<MacAddr>,
+    vlan: Option<u16>,
+    sampling_interval: Option<u64>,
+    sampling_space: Option<u64>,
+    sampler: Option<u64>,
+}
+
+impl Record {
+    /// One in how many packets was sampled, if the record says.
+    fn rate(&self) -> Option<u64> {
+        let interval = self.sampling_interval.filter(|&interval| interval > 0)?;
+        Some(match self.sampling_space {
+            Some(space) => interval.saturating_add(space) / interval,
+            None => interval,
+        })
+    }
+}
+
+/// NetFlow v9 and IPFIX decoding state of one collector socket.
+#[derive(Default)]
+pub struct Templates {
+    templates: HashMap<(Domain, u16), Template>,
+    /// Sampling rates announced in options records, by sampler id. Rates
+    /// announced without one apply to the whole domain and are kept as 0.
+    samplers: HashMap<(Domain, u64), u64>,
+}
+
+impl Templates {
+    /// Learn the templates of a NetFlow v9 or IPFIX datagram and account
+    /// the data records it has templates for.
+    pub fn parse(
+        &mut self,
+        exporter: IpAddr,
+        datagram: &[u8],
+        add: &mut dyn FnMut(RemoteSource, Sample),
+    ) -> Option<()> {
+        let mut r = Reader(datagram);
+        let version = r.u16()?;
+        let ipfix = version != NETFLOW9_VERSION;
+        // the IPFIX message length, the NetFlow v9 record count
+        let len = r.u16()? as usize;
+        // uptime (NetFlow v9 only), export time, sequence number
+        r.take(if ipfix { 8 } else { 12 })?;
+        let domain = Domain {
+            exporter,
+            version,
+            id: r.u32()?,
+        };
+        let sets = if ipfix {
+            datagram.get(IPFIX_HEADER_LEN..len)?
+        } else {
+            r.0
+        };
+        let protocol = if ipfix {
+            CollectorProtocol::Ipfix
+        } else {
+            CollectorProtocol::Netflow9
+        };
+
+        let mut r = Reader(sets);
+        while r.0.len() >= 4 {
+            let id = r.u16()?;
+            let len = (r.u16()? as usize).checked_sub(4)?;
+            let set = r.take(len)?;
+            match (id, ipfix) {
+                (NETFLOW9_TEMPLATE_SET, false) | (IPFIX_TEMPLATE_SET, true) => {
+                    self.templates(domain, set, ipfix)
+                }
+                (NETFLOW9_OPTIONS_TEMPLATE_SET, false) => {
+                    self.netflow9_options_templates(domain, set)
+                }
+                (IPFIX_OPTIONS_TEMPLATE_SET, true) => self.ipfix_options_templates(domain, set),
+                (id, _) if id >= FIRST_DATA_SET => self.data(domain, id, set, protocol, add),
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    fn insert(&mut self, domain: Domain, id: u16, template: Template) {
+        let key = (domain, id);
+        if self.templates.len() >= MAX_TEMPLATES && !self.templates.contains_key(&key) {
+            return;
+        }
+        self.templates.insert(key, template);
+    }
+
+    fn templates(&mut self, domain: Domain, set: &[u8], ipfix: bool) {
+        let mut r = Reader(set);
+        // anything shorter is padding
+        while r.0.len() >= 4 {
+            let (id, count) = match (r.u16(), r.u16()) {
+                (Some(id), Some(count)) => (id, count),
+                _ => return,
+            };
+            // an IPFIX template without fields withdraws the template
+            if count == 0 {
+                self.templates.remove(&(domain, id));
+                continue;
+            }
+            let fields = match fields(&mut r, count, ipfix) {
+                Some(fields) => fields,
+                None => return,
+            };
+            self.insert(
+                domain,
+                id,
+                Template {
+                    fields,
+                    options: false,
+                },
+            );
+        }
+    }
+
+    fn netflow9_options_templates(&mut self, domain: Domain, set: &[u8]) {
+        let mut r = Reader(set);
+        while r.0.len() >= 6 {
+            let (id, scope_len, option_len) = match (r.u16(), r.u16(), r.u16()) {
+                (Some(id), Some(scope_len), Some(option_len)) => (id, scope_len, option_len),
+                _ => return,
+            };
+            let scopes = fields(&mut r, scope_len / 4, false);
+            let options = fields(&mut r, option_len / 4, false);
+            let (mut scopes, options) = match (scopes, options) {
+                (Some(scopes), Some(options)) if !options.is_empty() => (scopes, options),
+                _ => return,
+            };
+            // scope types have numbers of their own
+            for scope in &mut scopes {
+                scope.id = UNKNOWN;
+            }
+            scopes.extend(options);
+            self.insert(
+                domain,
+                id,
+                Template {
+                    fields: scopes,
+                    options: true,
+                },
+            );
+        }
+    }
+
+    fn ipfix_options_templates(&mut self, domain: Domain, set: &[u8]) {
+        let mut r = Reader(set);
+        while r.0.len() >= 4 {
+            let (id, count) = match (r.u16(), r.u16()) {
+                (Some(id), Some(count)) => (id, count),
+                _ => return,
+            };
+            if count == 0 {
+                self.templates.remove(&(domain, id));
+                continue;
+            }
+            // scope field count, the scopes are ordinary elements
+            let fields = match (r.u16(), fields(&mut r, count, true)) {
+                (Some(_), Some(fields)) => fields,
+                _ => return,
+            };
+            self.insert(
+                domain,
+                id,
+                Template {
+                    fields,
+                    options: true,
+                },
+            );
+        }
+    }
+
+    fn data(
+        &mut self,
+        domain: Domain,
+        id: u16,
+        set: &[u8],
+        protocol: CollectorProtocol,
+        add: &mut dyn FnMut(RemoteSource, Sample),
+    ) {
+        // data arriving before its template is lost, exporters repeat
+        // their templates regularly
+        let template = match self.templates.get(&(domain, id)) {
+            Some(template) => template,
+            None => return,
+        };
+        let min_len: usize = template
+            .fields
+            .iter()
+            .map(|f| match f.len {
+                VARIABLE_LENGTH => 1,
+                len => len as usize,
+            })
+            .sum();
+        if min_len == 0 {
+            return;
+        }
+        let mut r = Reader(set);
+        let mut records = Vec::new();
+        // anything shorter is padding
+        while r.0.len() >= min_len {
+            match record(&mut r, &template.fields) {
+                Some(record) => records.push(record),
+                None => break,
+            }
+        }
+        if template.options {
+            for record in records {
+                if let Some(rate) = record.rate() {
+                    self.samplers
+                        .insert((domain, record.sampler.unwrap_or(0)), rate);
+                }
+            }
+            return;
+        }
+        for record in records {
+            let (source, destination) = match (record.source, record.destination) {
+                (Some(source), Some(destination)) => (source, destination),
+                _ => continue,
+            };
+            let rate = record
+                .rate()
+                .or_else(|| {
+                    let sampler = record.sampler?;
+                    self.samplers.get(&(domain, sampler)).copied()
+                })
+                .or_else(|| self.samplers.get(&(domain, 0)).copied())
+                .unwrap_or(1);
+            let remote = RemoteSource {
+                exporter: domain.exporter,
+                if_index: record.if_index,
+                protocol,
+            };
+            add(
+                remote,
+                Sample::Flow(FlowSample {
+                    source,
+                    destination,
+                    protocol: record.protocol,
+                    source_mac: record.source_mac,
+                    destination_mac: record.destination_mac,
+                    vlan: record.vlan,
+                    packets: record.packets.saturating_mul(rate),
+                    bytes: record.bytes.saturating_mul(rate),
+                }),
+            );
+        }
+    }
+}
+
+fn fields(r: &mut Reader, count: u16, ipfix: bool) -> Option<Vec<Field>> {
+    let mut fields = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut id = r.u16()?;
+        let len = r.u16()?;
+        if ipfix && id & ENTERPRISE_BIT != 0 {
+            r.u32()?; // enterprise number
+            id = UNKNOWN;
+        }
+        fields.push(Field { id, len });
+    }
+    Some(fields)
+}
+
+fn record(r: &mut Reader, fields: &[Field]) -> Option<Record> {
+    let mut record = Record::default();
+    for field in fields {
+        let len = match field.len {
+            VARIABLE_LENGTH => match r.u8()? {
+                255 => r.u16()? as usize,
+                len => len as usize,
+            },
+            len => len as usize,
+        };
+        let value = r.take(len)?;
+        match (field.id, len) {
+            (IN_BYTES, _) => record.bytes = unsigned(value),
+            (IN_PKTS, _) => record.packets = unsigned(value),
+            (PROTOCOL, 1) => record.protocol = value[0],
+            (IPV4_SRC_ADDR, 4) | (IPV6_SRC_ADDR, 16) => record.source = address(value),
+            (IPV4_DST_ADDR, 4) | (IPV6_DST_ADDR, 16) => record.destination = address(value),
+            (INPUT_SNMP, _) => record.if_index = unsigned(value) as u32,
+            (IN_SRC_MAC, 6) => record.source_mac = Some(mac(value)),
+            (IN_DST_MAC, 6) => record.destination_mac = Some(mac(value)),
+            (SRC_VLAN, _) => record.vlan = Some(unsigned(value) as u16 & 0x0fff),
+            (SAMPLING_INTERVAL | FLOW_SAMPLER_RANDOM_INTERVAL | SAMPLING_PACKET_INTERVAL, _) => {
+                record.sampling_interval = Some(unsigned(value))
+            }
+            (SAMPLING_PACKET_SPACE, _) => record.sampling_space = Some(unsigned(value)),
+            (FLOW_SAMPLER_ID | SELECTOR_ID, _) => record.sampler = Some(unsigned(value)),
+            _ => {}
+        }
+    }
+    Some(record)
+}
+
+/// Exporters may send counters in fewer bytes than the field's type has.
+fn unsigned(value: &[u8]) -> u64 {
+    value.iter().take(8).fold(0, |n, &b| (n << 8) | b as u64)
+}
+
+fn address(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(value).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(value).ok()?)),
+        _ => None,
+    }
+}
+
+fn mac(value: &[u8]) -> MacAddr {
+    MacAddr::new(value[0], value[1], value[2], value[3], value[4], value[5])
+}
@@ -0,0 +1,79 @@
+use super::{CollectorProtocol, Reader, RemoteSource, Sample};
+
+const ADDRESS_IPV4: u32 = 1;
+const ADDRESS_IPV6: u32 = 2;
+// sample and record formats, standard enterprise
+const FLOW_SAMPLE: u32 = 1;
+const EXPANDED_FLOW_SAMPLE: u32 = 3;
+const RAW_PACKET_HEADER: u32 = 1;
+const HEADER_ETHERNET: u32 = 1;
+const HEADER_IPV4: u32 = 11;
+const HEADER_IPV6: u32 = 12;
+
+/// Hand the raw packet headers of the flow samples in an sFlow v5 datagram
+/// to `add`. Counter samples and other record formats are skipped.
+pub fn parse(datagram: &[u8], add: &mut dyn FnMut(RemoteSource, Sample)) -> Option<()> {
+    let mut r = Reader(datagram);
+    r.u32()?; // version
+    let agent = match r.u32()? {
+        ADDRESS_IPV4 => r.ipv4()?,
+        ADDRESS_IPV6 => r.ipv6()?,
+        _ => return None,
+    };
+    r.take(12)?; // sub agent id, sequence number, uptime
+    let samples = r.u32()?;
+    for _ in 0..samples {
+        let format = r.u32()?;
+        let len = r.u32()? as usize;
+        let mut sample = Reader(r.take(len)?);
+        let if_index = match format {
+            FLOW_SAMPLE => {
+                // sequence number, then the source id with its type in
+                // the top byte
+                sample.u32()?;
+                sample.u32()? & 0x00ff_ffff
+            }
+            EXPANDED_FLOW_SAMPLE => {
+                sample.take(8)?; // sequence number, source id type
+                sample.u32()?
+            }
+            _ => continue,
+        };
+        let rate = sample.u32()?.max(1) as u64;
+        // sample pool, drops, input and output interface
+        sample.take(if format == FLOW_SAMPLE { 16 } else { 24 })?;
+        let source = RemoteSource {
+            exporter: agent,
+            if_index,
+            protocol: CollectorProtocol::Sflow,
+        };
+        let records = sample.u32()?;
+        for _ in 0..records {
+            let format = sample.u32()?;
+            let len = sample.u32()? as usize;
+            let mut record = Reader(sample.take(len)?);
+            if format != RAW_PACKET_HEADER {
+                continue;
+            }
+            let raw_ip = match record.u32()? {
+                HEADER_ETHERNET => false,
+                HEADER_IPV4 | HEADER_IPV6 => true,
+                _ => continue,
+            };
+            let frame_length = record.u32()? as u64;
+            record.u32()?; // stripped
+            let header_length = record.u32()? as usize;
+            let frame = record.take(header_length)?;
+            add(
+                source,
+                Sample::Frame {
+                    frame,
+                    raw_ip,
+                    packets: rate,
+                    bytes: frame_length * rate,
+                },
+            );
+        }
+    }
+    Some(())
+}
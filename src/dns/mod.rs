@@ -0,0 +1,160 @@
+//! Just enough of the DNS wire format to read answers off the network and
+//! to ask for PTR records.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub mod reverse;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+/// Compression pointers followed per name before giving up on a loop.
+const MAX_POINTERS: usize = 16;
+
+pub enum RecordData {
+    Address(IpAddr),
+    Name(String),
+}
+
+pub struct Record {
+    pub name: String,
+    pub record_type: u16,
+    pub data: RecordData,
+}
+
+pub struct Response {
+    /// Name of the first question, the one the client asked for. Multicast
+    /// DNS announcements come without.
+    pub question: Option<String>,
+    pub answers: Vec<Record>,
+}
+
+impl Response {
+    /// Successful responses only, errors and truncated answers are of no use.
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        let header = message.get(..HEADER_LEN)?;
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        let is_response = flags & 0x8000 != 0;
+        let opcode = (flags >> 11) & 0x0f;
+        let rcode = flags & 0x000f;
+        if !is_response || opcode != 0 || rcode != 0 {
+            return None;
+        }
+        let questions = u16::from_be_bytes([header[4], header[5]]);
+        let answers = u16::from_be_bytes([header[6], header[7]]);
+
+        let mut offset = HEADER_LEN;
+        let mut question = None;
+        for _ in 0..questions {
+            let (name, next) = read_name(message, offset)?;
+            question.get_or_insert(name);
+            offset = next + 4; // type, class
+        }
+
+        let mut records = Vec::with_capacity(answers as usize);
+        for _ in 0..answers {
+            let (name, next) = read_name(message, offset)?;
+            let fixed = message.get(next..next + 10)?;
+            let record_type = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let class = u16::from_be_bytes([fixed[2], fixed[3]]) & 0x7fff;
+            // ttl in fixed[4..8] is ignored, connections outlive it anyway
+            let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            let start = next + 10;
+            let rdata = message.get(start..start + len)?;
+            offset = start + len;
+            if class != CLASS_IN {
+                continue;
+            }
+            let data = match record_type {
+                TYPE_A => {
+                    let octets: [u8; 4] = rdata.try_into().ok()?;
+                    RecordData::Address(Ipv4Addr::from(octets).into())
+                }
+                TYPE_AAAA => {
+                    let octets: [u8; 16] = rdata.try_into().ok()?;
+                    RecordData::Address(Ipv6Addr::from(octets).into())
+                }
+                TYPE_CNAME | TYPE_PTR => RecordData::Name(read_name(message, start)?.0),
+                _ => continue,
+            };
+            records.push(Record {
+                name,
+                record_type,
+                data,
+            });
+        }
+        Some(Self {
+            question,
+            answers: records,
+        })
+    }
+}
+
+/// Read a possibly compressed name, returns it with the offset behind it.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                let label = message.get(offset + 1..offset + 1 + len)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.extend(label.iter().map(|&c| c.to_ascii_lowercase() as char));
+                offset += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let low = *message.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                offset = ((len & 0x3f) << 8) | low;
+            }
+            _ => return None,
+        }
+    }
+    Some((name, end.unwrap_or(offset + 1)))
+}
+
+/// Name of the PTR record for `ip`, `4.3.2.1.in-addr.arpa` for `1.2.3.4`.
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// A recursive query for the PTR record of `ip`.
+pub fn ptr_query(id: u16, ip: IpAddr) -> Vec<u8> {
+    let mut message = Vec::with_capacity(96);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in reverse_name(ip).split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    message
+}
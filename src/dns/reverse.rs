@@ -0,0 +1,152 @@
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tracing::warn;
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+
+use super::{ptr_query, RecordData, Response, TYPE_PTR};
+
+/// Lookups waiting for their turn, further addresses are asked for later.
+const MAX_PENDING: usize = 1024;
+/// Answers kept, failures included so they are not asked for again soon.
+const MAX_CACHED: usize = 65536;
+const CACHE_DURATION: Duration = Duration::from_secs(3600);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum Lookup {
+    Pending,
+    Done(Option<String>, Instant),
+}
+
+#[derive(Default)]
+struct State {
+    cache: HashMap<IpAddr, Lookup>,
+    queue: VecDeque<IpAddr>,
+}
+
+/// PTR lookups against one resolver, at most `rate` queries per second.
+pub struct ReverseDns {
+    resolver: SocketAddr,
+    rate: u32,
+    state: Mutex<State>,
+}
+
+impl ReverseDns {
+    pub fn new(resolver: SocketAddr, rate: u32) -> Self {
+        Self {
+            resolver,
+            rate: rate.max(1),
+            state: Default::default(),
+        }
+    }
+
+    /// The cached name of `ip`, queues a lookup if there is none yet.
+    pub fn lookup(&self, ip: IpAddr) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        match state.cache.get(&ip) {
+            Some(Lookup::Done(name, expires)) if *expires > Instant::now() => name.clone(),
+            Some(Lookup::Pending) => None,
+            _ => {
+                if state.queue.len() < MAX_PENDING {
+                    state.queue.push_back(ip);
+                    state.cache.insert(ip, Lookup::Pending);
+                }
+                None
+            }
+        }
+    }
+
+    fn finish(&self, ip: IpAddr, name: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if state.cache.len() >= MAX_CACHED {
+            state
+                .cache
+                .retain(|_, lookup| matches!(lookup, Lookup::Done(_, expires) if *expires > now));
+        }
+        if state.cache.len() >= MAX_CACHED {
+            state.cache.clear();
+        }
+        state
+            .cache
+            .insert(ip, Lookup::Done(name, now + CACHE_DURATION));
+    }
+
+    pub async fn run(&self) {
+        let bind: SocketAddr = match self.resolver {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = match UdpSocket::bind(bind).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!(error = ?e, "Reverse DNS unavailable, unable to bind socket");
+                return;
+            }
+        };
+        if let Err(e) = socket.connect(self.resolver).await {
+            warn!(resolver = %self.resolver, error = ?e, "Reverse DNS unavailable, unable to reach the resolver");
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(1) / self.rate);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut id: u16 = 0;
+        // queries go out at the rate limit without waiting for earlier
+        // answers, which are told apart by their id
+        let mut in_flight: HashMap<u16, (IpAddr, Instant)> = HashMap::new();
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            tokio::select! {
+                res = socket.recv(&mut buffer) => {
+                    // the resolver being unreachable shows up here as well,
+                    // those queries time out
+                    let answer = match res {
+                        Ok(len) => &buffer[..len],
+                        Err(_) => continue,
+                    };
+                    let ip = match answer.get(..2) {
+                        Some(&[high, low]) => match in_flight.remove(&u16::from_be_bytes([high, low])) {
+                            Some((ip, _)) => ip,
+                            None => continue,
+                        },
+                        _ => continue,
+                    };
+                    let name = Response::parse(answer).and_then(|response| {
+                        response.answers.into_iter().find_map(|record| {
+                            match (record.record_type, record.data) {
+                                (TYPE_PTR, RecordData::Name(name)) => Some(name),
+                                _ => None,
+                            }
+                        })
+                    });
+                    self.finish(ip, name);
+                }
+                _ = interval.tick() => {
+                    let now = Instant::now();
+                    in_flight.retain(|_, (ip, deadline)| {
+                        let waiting = *deadline > now;
+                        if !waiting {
+                            self.finish(*ip, None);
+                        }
+                        waiting
+                    });
+                    let ip = match self.state.lock().unwrap().queue.pop_front() {
+                        Some(ip) => ip,
+                        None => continue,
+                    };
+                    id = id.wrapping_add(1);
+                    while in_flight.contains_key(&id) {
+                        id = id.wrapping_add(1);
+                    }
+                    if socket.send(&ptr_query(id, ip)).await.is_err() {
+                        self.finish(ip, None);
+                        continue;
+                    }
+                    in_flight.insert(id, (ip, now + QUERY_TIMEOUT));
+                }
+            }
+        }
+    }
+}
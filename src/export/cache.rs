@@ -0,0 +1,148 @@
+use pnet::datalink::NetworkInterface;
+use pnet::util::MacAddr;
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::{ExportRecord, Exporter};
+
+const FIN: u8 = 0x01;
+const RST: u8 = 0x04;
+/// Flows kept at once, the cache is flushed early once it fills up.
+const MAX_FLOWS: usize = 65536;
+
+/// One direction of a conversation, the key of NetFlow and IPFIX records.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+struct Key {
+    source: IpAddr,
+    destination: IpAddr,
+    source_port: u16,
+    destination_port: u16,
+    protocol: u8,
+}
+
+struct Entry {
+    /// Sent by this host rather than received.
+    outbound: bool,
+    tcp_flags: u8,
+    packets: u64,
+    bytes: u64,
+    first: u64,
+    last: u64,
+}
+
+/// Flow cache of one capture, kept by the capture thread and expired into
+/// records on every flush.
+pub struct FlowCache {
+    exporter: Exporter,
+    /// ifIndex the records name as ingress or egress interface, 0 for files.
+    interface: u32,
+    mac: Option<MacAddr>,
+    addresses: Vec<IpAddr>,
+    flows: HashMap<Key, Entry>,
+}
+
+impl FlowCache {
+    pub fn new(exporter: Exporter, interface: Option<&NetworkInterface>) -> Self {
+        Self {
+            exporter,
+            interface: interface.map_or(0, |interface| interface.index),
+            mac: interface.and_then(|interface| interface.mac),
+            addresses: interface.map_or_else(Vec::new, |interface| {
+                interface.ips.iter().map(|network| network.ip()).collect()
+            }),
+            flows: HashMap::new(),
+        }
+    }
+
+    /// Whether a packet was sent through the interface rather than received:
+    /// it comes from the interface's MAC address, or from one of its IP
+    /// addresses on interfaces without link layer addresses.
+    fn outbound(&self, source_mac: Option<MacAddr>, source: IpAddr) -> bool {
+        match (self.mac, source_mac) {
+            (Some(mac), Some(source_mac)) => mac == source_mac,
+            _ => self.addresses.contains(&source),
+        }
+    }
+
+    /// Account one packet at `timestamp`, milliseconds since start. Ports
+    /// and TCP flags are zero for protocols without.
+    #[allow(clippy::too_many_arguments)]
+    pub fn observe(
+        &mut self,
+        source_mac: Option<MacAddr>,
+        source: IpAddr,
+        destination: IpAddr,
+        protocol: u8,
+        ports: (u16, u16),
+        tcp_flags: u8,
+        len: usize,
+        timestamp: u64,
+    ) {
+        let key = Key {
+            source,
+            destination,
+            source_port: ports.0,
+            destination_port: ports.1,
+            protocol,
+        };
+        if self.flows.len() >= MAX_FLOWS && !self.flows.contains_key(&key) {
+            return;
+        }
+        let outbound = self.outbound(source_mac, source);
+        let entry = self.flows.entry(key).or_insert(Entry {
+            outbound,
+            tcp_flags: 0,
+            packets: 0,
+            bytes: 0,
+            first: timestamp,
+            last: timestamp,
+        });
+        entry.tcp_flags |= tcp_flags;
+        entry.packets += 1;
+        entry.bytes += len as u64;
+        entry.last = timestamp;
+    }
+
+    /// Records of the flows which ended: finished TCP connections, flows
+    /// idle for the inactive timeout and flows older than the active
+    /// timeout, which start over. A full cache is expired as a whole.
+    pub fn expire(&mut self, timestamp: u64) {
+        let active = self.exporter.active_timeout;
+        let inactive = self.exporter.inactive_timeout;
+        let full = self.flows.len() >= MAX_FLOWS;
+        let mut records = Vec::new();
+        self.flows.retain(|key, entry| {
+            let expired = full
+                || entry.tcp_flags & (FIN | RST) != 0
+                || timestamp.saturating_sub(entry.last) >= inactive
+                || timestamp.saturating_sub(entry.first) >= active;
+            if expired {
+                records.push(ExportRecord {
+                    source: key.source,
+                    destination: key.destination,
+                    source_port: key.source_port,
+                    destination_port: key.destination_port,
+                    protocol: key.protocol,
+                    outbound: entry.outbound,
+                    tcp_flags: entry.tcp_flags,
+                    packets: entry.packets,
+                    bytes: entry.bytes,
+                    first: entry.first,
+                    last: entry.last,
+                });
+            }
+            !expired
+        });
+        if !records.is_empty() {
+            self.exporter.send(self.interface, records);
+        }
+    }
+}
+
+impl Drop for FlowCache {
+    /// The capture ended, whatever is left is exported.
+    fn drop(&mut self) {
+        self.expire(u64::MAX);
+    }
+}
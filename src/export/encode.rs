@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::{ExportProtocol, ExportRecord};
+
+const NETFLOW9_VERSION: u16 = 9;
+const IPFIX_VERSION: u16 = 10;
+const NETFLOW9_TEMPLATE_SET: u16 = 0;
+const IPFIX_TEMPLATE_SET: u16 = 2;
+const TEMPLATE_IPV4: u16 = 256;
+const TEMPLATE_IPV6: u16 = 257;
+/// Records per message, keeps IPv6 messages with templates below 1400
+/// bytes so they are not fragmented.
+const RECORDS_PER_MESSAGE: usize = 15;
+
+// NetFlow v9 field types, IPFIX uses the same numbers for its elements
+const IN_BYTES: u16 = 1;
+const IN_PKTS: u16 = 2;
+const PROTOCOL: u16 = 4;
+const TCP_FLAGS: u16 = 6;
+const L4_SRC_PORT: u16 = 7;
+const IPV4_SRC_ADDR: u16 = 8;
+const INPUT_SNMP: u16 = 10;
+const L4_DST_PORT: u16 = 11;
+const IPV4_DST_ADDR: u16 = 12;
+const OUTPUT_SNMP: u16 = 14;
+const LAST_SWITCHED: u16 = 21;
+const FIRST_SWITCHED: u16 = 22;
+const IPV6_SRC_ADDR: u16 = 27;
+const IPV6_DST_ADDR: u16 = 28;
+/// 0 for ingress, 1 for egress.
+const DIRECTION: u16 = 61;
+const FLOW_START_MILLISECONDS: u16 = 152;
+const FLOW_END_MILLISECONDS: u16 = 153;
+
+/// Message state per observation domain, one domain per interface.
+#[derive(Default)]
+struct Domain {
+    /// NetFlow v9 counts messages, IPFIX counts data records.
+    sequence: u32,
+    templates_sent: Option<Instant>,
+}
+
+pub struct Encoder {
+    protocol: ExportProtocol,
+    template_refresh: Duration,
+    start_time: Instant,
+    /// Wall clock time of `start_time` since the Unix epoch.
+    start: Duration,
+    domains: HashMap<u32, Domain>,
+}
+
+impl Encoder {
+    pub fn new(
+        protocol: ExportProtocol,
+        template_refresh: Duration,
+        start_time: Instant,
+        start: Duration,
+    ) -> Self {
+        Self {
+            protocol,
+            template_refresh,
+            start_time,
+            start,
+            domains: HashMap::new(),
+        }
+    }
+
+    /// Have the templates go along with the next message of every domain,
+    /// for a collector which was unreachable so far.
+    pub fn resend_templates(&mut self) {
+        for domain in self.domains.values_mut() {
+            domain.templates_sent = None;
+        }
+    }
+
+    /// Messages carrying `records`, templates go along with the first one
+    /// whenever they are due.
+    pub fn encode(&mut self, interface: u32, records: &[ExportRecord]) -> Vec<Vec<u8>> {
+        let uptime = self.start_time.elapsed();
+        let now = self.start + uptime;
+        let protocol = self.protocol;
+        let domain = self.domains.entry(interface).or_default();
+        let mut messages = Vec::new();
+        for chunk in records.chunks(RECORDS_PER_MESSAGE) {
+            let templates = match domain.templates_sent {
+                Some(sent) => sent.elapsed() >= self.template_refresh,
+                None => true,
+            };
+            if templates {
+                domain.templates_sent = Some(Instant::now());
+            }
+            let mut message = Message::new(protocol);
+            if templates {
+                message.templates();
+            }
+            for ipv6 in [false, true] {
+                let records: Vec<&ExportRecord> = chunk
+                    .iter()
+                    .filter(|r| r.source.is_ipv6() == ipv6)
+                    .collect();
+                if records.is_empty() {
+                    continue;
+                }
+                let template = if ipv6 { TEMPLATE_IPV6 } else { TEMPLATE_IPV4 };
+                let set = message.begin_set(template);
+                for record in records {
+                    message.record(record, interface, self.start);
+                }
+                message.end_set(set);
+            }
+            message.finish(
+                interface,
+                domain.sequence,
+                uptime.as_millis() as u32,
+                now.as_secs() as u32,
+            );
+            domain.sequence = domain.sequence.wrapping_add(match protocol {
+                ExportProtocol::Netflow9 => 1,
+                ExportProtocol::Ipfix => chunk.len() as u32,
+            });
+            messages.push(message.buffer);
+        }
+        messages
+    }
+}
+
+struct Message {
+    protocol: ExportProtocol,
+    buffer: Vec<u8>,
+    /// Template and data records, the NetFlow v9 header counts them.
+    count: u16,
+}
+
+impl Message {
+    fn new(protocol: ExportProtocol) -> Self {
+        let header_len = match protocol {
+            ExportProtocol::Netflow9 => 20,
+            ExportProtocol::Ipfix => 16,
+        };
+        Self {
+            protocol,
+            buffer: vec![0; header_len],
+            count: 0,
+        }
+    }
+
+    fn fields(&self, ipv6: bool) -> [(u16, u16); 13] {
+        let (source, destination, len) = if ipv6 {
+            (IPV6_SRC_ADDR, IPV6_DST_ADDR, 16)
+        } else {
+            (IPV4_SRC_ADDR, IPV4_DST_ADDR, 4)
+        };
+        let (first, last, time_len) = match self.protocol {
+            ExportProtocol::Netflow9 => (FIRST_SWITCHED, LAST_SWITCHED, 4),
+            ExportProtocol::Ipfix => (FLOW_START_MILLISECONDS, FLOW_END_MILLISECONDS, 8),
+        };
+        [
+            (source, len),
+            (destination, len),
+            (L4_SRC_PORT, 2),
+            (L4_DST_PORT, 2),
+            (PROTOCOL, 1),
+            (TCP_FLAGS, 1),
+            (IN_PKTS, 8),
+            (IN_BYTES, 8),
+            (first, time_len),
+            (last, time_len),
+            (INPUT_SNMP, 4),
+            (OUTPUT_SNMP, 4),
+            (DIRECTION, 1),
+        ]
+    }
+
+    fn templates(&mut self) {
+        let set = self.begin_set(match self.protocol {
+            ExportProtocol::Netflow9 => NETFLOW9_TEMPLATE_SET,
+            ExportProtocol::Ipfix => IPFIX_TEMPLATE_SET,
+        });
+        for (template, ipv6) in [(TEMPLATE_IPV4, false), (TEMPLATE_IPV6, true)] {
+            let fields = self.fields(ipv6);
+            self.u16(template);
+            self.u16(fields.len() as u16);
+            for (field, len) in fields {
+                self.u16(field);
+                self.u16(len);
+            }
+            self.count += 1;
+        }
+        self.end_set(set);
+    }
+
+    fn record(&mut self, record: &ExportRecord, interface: u32, start: Duration) {
+        for address in [record.source, record.destination] {
+            match address {
+                IpAddr::V4(ip) => self.buffer.extend_from_slice(&ip.octets()),
+                IpAddr::V6(ip) => self.buffer.extend_from_slice(&ip.octets()),
+            }
+        }
+        self.u16(record.source_port);
+        self.u16(record.destination_port);
+        self.buffer.push(record.protocol);
+        self.buffer.push(record.tcp_flags);
+        self.buffer.extend_from_slice(&record.packets.to_be_bytes());
+        self.buffer.extend_from_slice(&record.bytes.to_be_bytes());
+        match self.protocol {
+            // milliseconds of system uptime
+            ExportProtocol::Netflow9 => {
+                self.u32(record.first as u32);
+                self.u32(record.last as u32);
+            }
+            // milliseconds since the Unix epoch
+            ExportProtocol::Ipfix => {
+                let start = start.as_millis() as u64;
+                self.buffer
+                    .extend_from_slice(&(start + record.first).to_be_bytes());
+                self.buffer
+                    .extend_from_slice(&(start + record.last).to_be_bytes());
+            }
+        }
+        // the other side of the interface is not known
+        let (input, output) = if record.outbound {
+            (0, interface)
+        } else {
+            (interface, 0)
+        };
+        self.u32(input);
+        self.u32(output);
+        self.buffer.push(record.outbound as u8);
+        self.count += 1;
+    }
+
+    /// Write a set header, returns where it starts.
+    fn begin_set(&mut self, id: u16) -> usize {
+        let start = self.buffer.len();
+        self.u16(id);
+        self.u16(0);
+        start
+    }
+
+    fn end_set(&mut self, start: usize) {
+        // sets are padded to a multiple of four bytes
+        let padding = (4 - (self.buffer.len() - start) % 4) % 4;
+        self.buffer.resize(self.buffer.len() + padding, 0);
+        let len = (self.buffer.len() - start) as u16;
+        self.buffer[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    fn finish(&mut self, domain: u32, sequence: u32, uptime: u32, unix_seconds: u32) {
+        let mut header = Vec::with_capacity(20);
+        match self.protocol {
+            ExportProtocol::Netflow9 => {
+                header.extend_from_slice(&NETFLOW9_VERSION.to_be_bytes());
+                header.extend_from_slice(&self.count.to_be_bytes());
+                header.extend_from_slice(&uptime.to_be_bytes());
+                header.extend_from_slice(&unix_seconds.to_be_bytes());
+            }
+            ExportProtocol::Ipfix => {
+                header.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+                header.extend_from_slice(&(self.buffer.len() as u16).to_be_bytes());
+                header.extend_from_slice(&unix_seconds.to_be_bytes());
+            }
+        }
+        header.extend_from_slice(&sequence.to_be_bytes());
+        header.extend_from_slice(&domain.to_be_bytes());
+        self.buffer[..header.len()].copy_from_slice(&header);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+}
@@ -0,0 +1,312 @@
+//! NetFlow v9 and IPFIX export of the flows seen on listened interfaces.
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::Shared;
+use futures::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod cache;
+mod encode;
+
+pub use cache::FlowCache;
+use encode::Encoder;
+
+/// Batches of records waiting for the sockets.
+const CHANNEL_CAPACITY: usize = 64;
+/// Collectors which could not be reached are tried again this often.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportProtocol {
+    Netflow9,
+    #[default]
+    Ipfix,
+}
+
+impl FromStr for ExportProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned())).map_err(|_| {
+            format!(
+                "unknown export protocol {:?} (expected netflow9 or ipfix)",
+                s
+            )
+        })
+    }
+}
+
+/// One expired flow, timestamps in milliseconds since start.
+pub struct ExportRecord {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub protocol: u8,
+    /// Egress rather than ingress traffic of the interface.
+    pub outbound: bool,
+    pub tcp_flags: u8,
+    pub packets: u64,
+    /// Frame bytes, link layer header included.
+    pub bytes: u64,
+    pub first: u64,
+    pub last: u64,
+}
+
+struct Batch {
+    interface: u32,
+    records: Vec<ExportRecord>,
+}
+
+/// Handle of the export task, cloned into every capture.
+#[derive(Clone)]
+pub struct Exporter {
+    tx: mpsc::Sender<Batch>,
+    /// Fires once the export task is done.
+    done: Shared<oneshot::Receiver<()>>,
+    /// Milliseconds.
+    active_timeout: u64,
+    inactive_timeout: u64,
+}
+
+impl Exporter {
+    pub fn start(
+        collectors: Vec<SocketAddr>,
+        protocol: ExportProtocol,
+        active_timeout: Duration,
+        inactive_timeout: Duration,
+        template_refresh: Duration,
+        start_time: Instant,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        // the wall clock time of `start_time`
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_sub(start_time.elapsed());
+        let encoder = Encoder::new(protocol, template_refresh, start_time, start);
+        let (done_tx, done) = oneshot::channel();
+        tokio::spawn(async move {
+            run(collectors, encoder, rx).await;
+            drop(done_tx);
+        });
+        Self {
+            tx,
+            done: done.shared(),
+            active_timeout: active_timeout.as_millis() as u64,
+            inactive_timeout: inactive_timeout.as_millis() as u64,
+        }
+    }
+
+    /// Called from the capture thread, records are dropped rather than
+    /// waited for when the sockets are behind.
+    fn send(&mut self, interface: u32, records: Vec<ExportRecord>) {
+        let _ = self.tx.try_send(Batch { interface, records });
+    }
+
+    /// Sends what is queued and takes nothing more, on shutdown once the
+    /// captures ended.
+    pub async fn finish(&self) {
+        self.tx.clone().close_channel();
+        let _ = self.done.clone().await;
+    }
+}
+
+struct Collector {
+    address: SocketAddr,
+    socket: Option<UdpSocket>,
+    /// When setting up the socket failed last.
+    failed: Option<Instant>,
+}
+
+impl Collector {
+    /// Sets up the socket unless there is one or it failed too recently,
+    /// returns whether it came up after failing before.
+    async fn connect(&mut self) -> bool {
+        if self.socket.is_some() || self.failed.is_some_and(|t| t.elapsed() < RETRY_INTERVAL) {
+            return false;
+        }
+        let collector = self.address;
+        match connect(collector).await {
+            Ok(socket) => {
+                self.socket = Some(socket);
+                let recovered = self.failed.take().is_some();
+                if recovered {
+                    info!(%collector, "Flow export available again");
+                }
+                recovered
+            }
+            Err(e) => {
+                if self.failed.is_none() {
+                    warn!(%collector, error = ?e, "Flow export unavailable, trying again every {} seconds", RETRY_INTERVAL.as_secs());
+                }
+                self.failed = Some(Instant::now());
+                false
+            }
+        }
+    }
+}
+
+async fn connect(collector: SocketAddr) -> io::Result<UdpSocket> {
+    let bind: SocketAddr = match collector {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(collector).await?;
+    Ok(socket)
+}
+
+async fn run(collectors: Vec<SocketAddr>, mut encoder: Encoder, mut rx: mpsc::Receiver<Batch>) {
+    let mut collectors: Vec<Collector> = collectors
+        .into_iter()
+        .map(|address| Collector {
+            address,
+            socket: None,
+            failed: None,
+        })
+        .collect();
+    // up front, so an unreachable collector is reported right away
+    for collector in &mut collectors {
+        collector.connect().await;
+    }
+    while let Some(batch) = rx.next().await {
+        for collector in &mut collectors {
+            if collector.connect().await {
+                encoder.resend_templates();
+            }
+        }
+        for message in encoder.encode(batch.interface, &batch.records) {
+            for socket in collectors.iter().filter_map(|c| c.socket.as_ref()) {
+                // a collector which is down must not hold up the others
+                let _ = socket.send(&message).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    /// Template id to its (field, length) list, and the data records as
+    /// field to value, of one message.
+    type Decoded = (HashMap<u16, Vec<(u16, u16)>>, Vec<HashMap<u16, Vec<u8>>>);
+
+    fn u16_at(buffer: &[u8], at: usize) -> u16 {
+        u16::from_be_bytes([buffer[at], buffer[at + 1]])
+    }
+
+    fn decode(message: &[u8], protocol: ExportProtocol) -> Decoded {
+        let (version, header_len, template_set) = match protocol {
+            ExportProtocol::Netflow9 => (9, 20, 0),
+            ExportProtocol::Ipfix => (10, 16, 2),
+        };
+        assert_eq!(u16_at(message, 0), version);
+        if protocol == ExportProtocol::Ipfix {
+            assert_eq!(u16_at(message, 2) as usize, message.len());
+        }
+        let mut templates = HashMap::new();
+        let mut records = Vec::new();
+        let mut at = header_len;
+        while at < message.len() {
+            let id = u16_at(message, at);
+            let end = at + u16_at(message, at + 2) as usize;
+            let mut field = at + 4;
+            if id == template_set {
+                while field + 4 <= end {
+                    let template = u16_at(message, field);
+                    let count = u16_at(message, field + 2) as usize;
+                    let fields: Vec<(u16, u16)> = (0..count)
+                        .map(|i| {
+                            let at = field + 4 + i * 4;
+                            (u16_at(message, at), u16_at(message, at + 2))
+                        })
+                        .collect();
+                    field += 4 + count * 4;
+                    templates.insert(template, fields);
+                }
+            } else {
+                let fields = &templates[&id];
+                let record_len: usize = fields.iter().map(|(_, len)| *len as usize).sum();
+                while field + record_len <= end {
+                    let mut record = HashMap::new();
+                    for (id, len) in fields {
+                        let len = *len as usize;
+                        record.insert(*id, message[field..field + len].to_vec());
+                        field += len;
+                    }
+                    records.push(record);
+                }
+            }
+            at = end;
+        }
+        (templates, records)
+    }
+
+    fn record(outbound: bool) -> ExportRecord {
+        ExportRecord {
+            source: Ipv4Addr::new(192, 0, 2, 1).into(),
+            destination: Ipv4Addr::new(198, 51, 100, 2).into(),
+            source_port: 40000,
+            destination_port: 443,
+            protocol: 6,
+            outbound,
+            tcp_flags: 0x1b,
+            packets: 10,
+            bytes: 4000,
+            first: 100,
+            last: 900,
+        }
+    }
+
+    #[tokio::test]
+    async fn exported_records_decode() {
+        for protocol in [ExportProtocol::Netflow9, ExportProtocol::Ipfix] {
+            let collector = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut exporter = Exporter::start(
+                vec![collector.local_addr().unwrap()],
+                protocol,
+                Duration::from_secs(60),
+                Duration::from_secs(15),
+                Duration::from_secs(600),
+                Instant::now(),
+            );
+            exporter.send(3, vec![record(false), record(true)]);
+            exporter.finish().await;
+
+            let mut buffer = [0; 2048];
+            let len = collector.recv(&mut buffer).await.unwrap();
+            let (templates, records) = decode(&buffer[..len], protocol);
+            assert_eq!(templates.len(), 2);
+            assert_eq!(templates[&256][0], (8, 4));
+            assert_eq!(templates[&257][0], (27, 16));
+            assert_eq!(records.len(), 2);
+            for (record, outbound) in records.iter().zip([false, true]) {
+                assert_eq!(record[&8], [192, 0, 2, 1]);
+                assert_eq!(record[&12], [198, 51, 100, 2]);
+                assert_eq!(record[&7], 40000u16.to_be_bytes());
+                assert_eq!(record[&11], 443u16.to_be_bytes());
+                assert_eq!(record[&4], [6]);
+                assert_eq!(record[&2], 10u64.to_be_bytes());
+                assert_eq!(record[&1], 4000u64.to_be_bytes());
+                // ingress and egress interface, and the direction
+                let (input, output) = if outbound { (0u32, 3u32) } else { (3, 0) };
+                assert_eq!(record[&10], input.to_be_bytes());
+                assert_eq!(record[&14], output.to_be_bytes());
+                assert_eq!(record[&61], [outbound as u8]);
+            }
+        }
+    }
+}
@@ -52,6 +52,7 @@ pub async fn file_send(
     }
 }
 
+#[allow(clippy::single_match, clippy::redundant_pattern_matching)]
 fn convert_data(
     data: &'static [u8],
     accept_encoding: Option<&HeaderValue>,
@@ -0,0 +1,22 @@
+use bytes::Bytes;
+use futures::{channel::mpsc::channel, SinkExt};
+use http_body_util::StreamBody;
+use hyper::{body::Frame, header, http::HeaderValue, Response};
+
+use crate::{statistics::CaptureCounters, AppContext, ResponseType};
+
+pub async fn metrics(context: &AppContext) -> ResponseType {
+    let message = {
+        let map = context.map.lock().await;
+        CaptureCounters::metrics(map.iter().map(|(name, s)| (name, s.counters())))
+    };
+    let (mut tx, rx) = channel(1);
+    let _ = tx.send(Ok(Frame::data(Bytes::from(message)))).await;
+    let body = StreamBody::new(rx);
+    let mut response = Response::new(body);
+    response.headers_mut().append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
@@ -1,7 +1,11 @@
 mod file_send;
+mod metrics;
+mod neighbors;
 mod not_found;
 
 use file_send::file_send;
+use metrics::metrics;
+use neighbors::neighbors;
 use not_found::not_found;
 
 use std::{convert::Infallible, net::SocketAddr};
@@ -11,12 +15,14 @@ use crate::{AppContext, ResponseType};
 use hyper::{body::Incoming, Method, Request};
 
 pub async fn on_http(
-    _: &AppContext,
+    context: &AppContext,
     _: SocketAddr,
     req: Request<Incoming>,
 ) -> Result<ResponseType, Infallible> {
     match (req.method(), req.uri().path()) {
         (&Method::GET | &Method::HEAD, "" | "/") => file_send(&req, "index.html").await,
+        (&Method::GET, "/metrics") => Ok(metrics(context).await),
+        (&Method::GET, "/neighbors") => Ok(neighbors(context).await),
         (&Method::GET | &Method::HEAD, path) => file_send(&req, &path[1..]).await,
         (m, path) => Ok(not_found(format!("Unknown request {:?} {:?}", m, path)).await),
     }
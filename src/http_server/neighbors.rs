@@ -0,0 +1,19 @@
+use bytes::Bytes;
+use futures::{channel::mpsc::channel, SinkExt};
+use http_body_util::StreamBody;
+use hyper::{body::Frame, header, http::HeaderValue, Response};
+
+use crate::{AppContext, ResponseType};
+
+pub async fn neighbors(context: &AppContext) -> ResponseType {
+    let message = context.neighbors_to_json().await.to_string();
+    let (mut tx, rx) = channel(1);
+    let _ = tx.send(Ok(Frame::data(Bytes::from(message)))).await;
+    let body = StreamBody::new(rx);
+    let mut response = Response::new(body);
+    response.headers_mut().append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
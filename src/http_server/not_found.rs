@@ -12,5 +12,5 @@ pub async fn not_found(error: impl std::fmt::Debug) -> ResponseType {
     let body = StreamBody::new(rx);
     let mut response = Response::new(body);
     *(response.status_mut()) = StatusCode::NOT_FOUND;
-    return response;
+    response
 }
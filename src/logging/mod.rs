@@ -0,0 +1,108 @@
+//! Diagnostics through `tracing`: filtered by `--log-level` or `RUST_LOG`,
+//! formatted as text or JSON lines, and written to stdout, journald or
+//! syslog.
+
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+
+use crate::alert::SyslogTarget;
+
+mod syslog;
+
+use syslog::Syslog;
+
+/// The filter when neither `--log-level` nor `RUST_LOG` is given.
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of the event and of the
+    /// spans it happened in.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| format!("unknown log format {:?} (expected text or json)", s))
+    }
+}
+
+pub enum LogOutput {
+    Stdout,
+    Journald,
+    Syslog(SyslogTarget),
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Installs the global subscriber. Runs first thing, so the capture helper
+/// forked later logs the same way. An output which cannot be opened falls
+/// back to stdout.
+pub fn init(filter: Option<&str>, format: LogFormat, output: LogOutput) {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter).expect("Invalid log level"),
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+        }
+    };
+    let (layer, unavailable) = match output {
+        LogOutput::Stdout => (stdout(format), None),
+        #[cfg(target_os = "linux")]
+        LogOutput::Journald => match tracing_journald::layer() {
+            Ok(layer) => (layer.boxed(), None),
+            Err(e) => (stdout(format), Some(("journald", e))),
+        },
+        #[cfg(not(target_os = "linux"))]
+        LogOutput::Journald => (
+            stdout(format),
+            Some((
+                "journald",
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "journald is only available on linux",
+                ),
+            )),
+        ),
+        // syslog stamps the time itself
+        LogOutput::Syslog(target) => match Syslog::open(&target) {
+            Ok(syslog) => (formatted(format, syslog, false, false), None),
+            Err(e) => (stdout(format), Some(("syslog", e))),
+        },
+    };
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .init();
+    if let Some((output, e)) = unavailable {
+        tracing::warn!(error = ?e, "Logging to {} unavailable, logging to stdout instead", output);
+    }
+}
+
+fn stdout(format: LogFormat) -> BoxedLayer {
+    formatted(format, io::stdout, io::stdout().is_terminal(), true)
+}
+
+fn formatted<W>(format: LogFormat, writer: W, ansi: bool, timestamps: bool) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match (format, timestamps) {
+        (LogFormat::Text, true) => layer.boxed(),
+        (LogFormat::Text, false) => layer.without_time().boxed(),
+        (LogFormat::Json, true) => layer.json().boxed(),
+        (LogFormat::Json, false) => layer.json().without_time().boxed(),
+    }
+}
@@ -0,0 +1,127 @@
+//! Formatted events sent to syslog, one datagram each, in the BSD format
+//! like alert notifications.
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use crate::alert::SyslogTarget;
+
+/// Facility `daemon`.
+const FACILITY: u8 = 3;
+
+enum Socket {
+    Udp(UdpSocket),
+    /// Sent to by path every time, so a restarted syslog daemon is found
+    /// again.
+    #[cfg(unix)]
+    Unix(UnixDatagram, PathBuf),
+}
+
+pub struct Syslog {
+    socket: Socket,
+}
+
+impl Syslog {
+    pub fn open(target: &SyslogTarget) -> io::Result<Self> {
+        let socket = match target {
+            SyslogTarget::Udp(address) => {
+                let bind: SocketAddr = match address {
+                    SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+                    SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(address)?;
+                Socket::Udp(socket)
+            }
+            #[cfg(unix)]
+            SyslogTarget::Unix(path) => Socket::Unix(UnixDatagram::unbound()?, path.clone()),
+            #[cfg(not(unix))]
+            SyslogTarget::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix sockets are unavailable",
+                ))
+            }
+        };
+        Ok(Self { socket })
+    }
+
+    fn send(&self, message: &[u8]) {
+        // nowhere left to report a failure to
+        let _ = match &self.socket {
+            Socket::Udp(socket) => socket.send(message),
+            #[cfg(unix)]
+            Socket::Unix(socket, path) => socket.send_to(message, path),
+        };
+    }
+
+    fn line(&self, severity: u8) -> Line<'_> {
+        Line {
+            syslog: self,
+            severity,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// One formatted event, sent once complete.
+pub struct Line<'a> {
+    syslog: &'a Syslog,
+    severity: u8,
+    buffer: Vec<u8>,
+}
+
+impl Write for Line<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Line<'_> {
+    fn drop(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        // the process id is taken each time, the capture helper is a fork
+        let message = format!(
+            "<{}>network_view[{}]: {}",
+            FACILITY * 8 + self.severity,
+            std::process::id(),
+            String::from_utf8_lossy(&self.buffer).trim()
+        );
+        self.syslog.send(message.as_bytes());
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Line<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.line(severity(&Level::INFO))
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.line(severity(meta.level()))
+    }
+}
+
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
@@ -1,20 +1,41 @@
 use argh::FromArgs;
 
+mod alert;
+mod capture;
+mod collector;
+mod dns;
+mod export;
 mod http_server;
+mod logging;
+mod privilege;
+mod shutdown;
 mod statistics;
 mod tls;
+mod vendor;
+mod watcher;
 mod websocket;
 
+use alert::{Alerts, Sinks, Smtp, SyslogTarget};
+use capture::Backend;
+use dns::reverse::ReverseDns;
+use export::{ExportProtocol, Exporter};
 use http_server::on_http;
+use logging::{LogFormat, LogOutput};
+use privilege::Privileges;
+use shutdown::Shutdown;
 use statistics::{statistics, InterfaceStatistics};
+use vendor::Vendors;
+use watcher::InterfaceEvent;
 use websocket::on_websocket;
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{convert::Infallible, net::SocketAddr};
 
 use bytes::Bytes;
 use futures::channel::mpsc;
+use futures::future::Either;
 use futures::lock::Mutex;
 use futures::{Future, SinkExt, StreamExt};
 use http_body_util::StreamBody;
@@ -28,29 +49,83 @@ use hyper::{
 };
 use hyper::{Method, Response, StatusCode, Version};
 use hyper_util::rt::TokioIo;
+use serde_json::Value;
 
+use tokio::sync::broadcast;
 use tokio_native_tls::native_tls;
 use tokio_tungstenite::{tungstenite, WebSocketStream};
+use tracing::{debug, error, info, warn, Instrument};
 
 use crate::tls::{default_certs, default_keys};
 
 pub type ResponseUnit = Result<Frame<Bytes>, Box<dyn std::error::Error + Send + Sync>>;
 pub type ResponseType = Response<StreamBody<mpsc::Receiver<ResponseUnit>>>;
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let opt: Options = argh::from_env();
+    let output = match &opt.log_syslog {
+        Some(s) => LogOutput::Syslog(parse_syslog(s)),
+        None if opt.log_journald => LogOutput::Journald,
+        None => LogOutput::Stdout,
+    };
+    logging::init(
+        opt.log_level.as_deref(),
+        opt.log_format.unwrap_or_default(),
+        output,
+    );
+    let runtime = || tokio::runtime::Runtime::new().unwrap();
+    if opt.benchmark {
+        runtime().block_on(statistics::benchmark::benchmark());
+        return;
+    }
     let addr = match &opt.listen_address {
         Some(s) => s.as_str(),
         None => "localhost:7200",
     };
 
-    let (listener, cert_source, key_source) = futures::join!(
-        tokio::net::TcpListener::bind(addr),
+    // forked first, the helper is to hold nothing but its socket
+    if opt.user.is_some() {
+        privilege::start_helper().expect("Failed to start the capture helper");
+    }
+    // bound before the runtime starts its threads, capabilities are per
+    // thread and only the ones started after dropping them go without
+    let listener = std::net::TcpListener::bind(addr).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let mut privileges = match &opt.user {
+        Some(user) => {
+            privilege::switch_user(user).expect("Failed to switch user");
+            Privileges::detect()
+        }
+        None if opt.drop_privileges => {
+            privilege::drop_privileges().expect("Failed to drop privileges")
+        }
+        None => Privileges::detect(),
+    };
+    if opt.seccomp {
+        privilege::apply_seccomp().expect("Failed to apply the seccomp filter");
+        privileges.seccomp = true;
+    }
+    let missing = privileges.missing();
+    if !missing.is_empty() {
+        warn!(
+            "Missing {} for live captures: {}",
+            missing.join(" and "),
+            privilege::HINT
+        );
+    }
+
+    let runtime = runtime();
+    runtime.block_on(serve(opt, listener, privileges));
+    // whatever did not wind down before the deadline is left behind
+    runtime.shutdown_background();
+}
+
+async fn serve(opt: Options, listener: std::net::TcpListener, privileges: Privileges) {
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    let (cert_source, key_source) = futures::join!(
         read_file(&opt.certificate, "Failed to read certificate file"),
         read_file(&opt.private_key, "Failed to read private key file"),
     );
-    let listener = listener.unwrap();
 
     let certs = match &cert_source {
         Some(v) => v.as_slice(),
@@ -72,18 +147,118 @@ async fn main() {
     let acceptor = builder.build().unwrap();
     let acceptor = tokio_native_tls::TlsAcceptor::from(acceptor);
 
-    println!("listen on https://{:?}", listener.local_addr().unwrap());
+    info!("listen on https://{:?}", listener.local_addr().unwrap());
+
+    let (signal, shutdown) = Shutdown::new();
+    tokio::spawn(shutdown::on_signal(signal));
 
     let (mut tx, rx) = mpsc::channel(0);
-    tokio::spawn(async move { while let Ok(_) = tx.send(listener.accept().await).await {} });
+    let accepting = shutdown.clone();
+    tokio::spawn(async move {
+        // once shutting down the listener is dropped and `rx` ends
+        let accept = async { while tx.send(listener.accept().await).await.is_ok() {} };
+        futures::pin_mut!(accept);
+        futures::future::select(accept, accepting.started()).await;
+    });
+
+    let vendors = load_vendors(&opt.oui_file);
+    let reverse_dns = match &opt.reverse_dns {
+        Some(resolver) => {
+            let reverse_dns = Arc::new(ReverseDns::new(
+                parse_address(resolver, 53).expect("Invalid reverse DNS resolver address"),
+                opt.reverse_dns_rate.unwrap_or(10),
+            ));
+            let runner = reverse_dns.clone();
+            tokio::spawn(async move { runner.run().await });
+            Some(reverse_dns)
+        }
+        None => None,
+    };
+
+    let start_time = std::time::Instant::now();
+    let exporter = if opt.export.is_empty() {
+        None
+    } else {
+        let protocol = opt.export_protocol.unwrap_or_default();
+        let port = match protocol {
+            ExportProtocol::Netflow9 => 2055,
+            ExportProtocol::Ipfix => 4739,
+        };
+        let collectors = opt
+            .export
+            .iter()
+            .map(|s| parse_address(s, port).expect("Invalid flow collector address"))
+            .collect();
+        Some(Exporter::start(
+            collectors,
+            protocol,
+            Duration::from_secs(opt.active_timeout.unwrap_or(60)),
+            Duration::from_secs(opt.inactive_timeout.unwrap_or(15)),
+            Duration::from_secs(opt.template_refresh.unwrap_or(60)),
+            start_time,
+        ))
+    };
+
+    let alerts = match &opt.alert_rules {
+        Some(path) => {
+            let rules = std::fs::read(path).expect("Failed to read alert rules file");
+            let rules = serde_json::from_slice(&rules).expect("Invalid alert rules file");
+            let syslog = opt.alert_syslog.as_deref().map(parse_syslog);
+            let smtp = match &opt.alert_smtp {
+                Some(_) if opt.alert_mail_to.is_empty() => {
+                    warn!("No --alert-mail-to given, alerts are not mailed");
+                    None
+                }
+                Some(relay) => Some(Smtp {
+                    relay: parse_address(relay, 25).expect("Invalid mail relay address"),
+                    from: opt
+                        .alert_mail_from
+                        .clone()
+                        .unwrap_or_else(|| "network_view@localhost".to_owned()),
+                    to: opt.alert_mail_to.clone(),
+                }),
+                None => None,
+            };
+            let sinks = Sinks {
+                webhooks: opt
+                    .alert_webhook
+                    .iter()
+                    .map(|s| s.parse().expect("Invalid alert webhook URL"))
+                    .collect(),
+                syslog,
+                smtp,
+            };
+            Some(Arc::new(Alerts::start(rules, sinks)))
+        }
+        None => None,
+    };
 
     let http1_service = http1::Builder::new();
     let http2_service = http2::Builder::new(TokioExecutor);
-    let start_time = std::time::Instant::now();
     let context: AppContext = AppContext {
-        start_time: start_time.clone(),
+        start_time,
         map: Default::default(),
+        capture_backend: opt.capture_backend.unwrap_or(if privileges.helper {
+            Backend::AfPacket
+        } else {
+            Backend::default()
+        }),
+        vendors: Arc::new(vendors),
+        reverse_dns,
+        exporter,
+        alerts: alerts.clone(),
+        interface_events: broadcast::channel(watcher::CHANNEL_CAPACITY).0,
+        privileges,
+        shutdown,
     };
+    tokio::spawn(watcher::run(context.clone()));
+
+    for address in &opt.collect {
+        let address = parse_address(address, 2055).expect("Invalid flow collector listen address");
+        let collector = collector::run(address, start_time, context.map.clone())
+            .instrument(tracing::info_span!("collector", %address));
+        tokio::spawn(collector);
+    }
 
     let acceptor = &acceptor;
     let http1_service = &http1_service;
@@ -95,37 +270,92 @@ async fn main() {
             Ok(r) => r,
             Err(_) => return,
         };
-        match acceptor.accept(stream).await {
-            Ok(stream) => {
-                let is_h2 = false; // @TODO: wait native_tls support alpn
-                let stream = TokioIo::new(stream);
-                let res = if is_h2 {
-                    let handle = |request| {
-                        let context = context.clone();
-                        let addr = addr.clone();
-                        async move { on_http(&context, addr, request).await }
+        async move {
+            match acceptor.accept(stream).await {
+                Ok(stream) => {
+                    let is_h2 = false; // @TODO: wait native_tls support alpn
+                    let stream = TokioIo::new(stream);
+                    // requests in flight are answered once shutting down, then
+                    // the connection is closed
+                    let res = if is_h2 {
+                        let handle = |request| {
+                            let context = context.clone();
+                            async move { on_http(&context, addr, request).await }
+                        };
+                        let connection = http2_service.serve_connection(stream, service_fn(handle));
+                        futures::pin_mut!(connection);
+                        match futures::future::select(connection, context.shutdown.started()).await
+                        {
+                            Either::Left((res, _)) => res,
+                            Either::Right((_, mut connection)) => {
+                                connection.as_mut().graceful_shutdown();
+                                connection.await
+                            }
+                        }
+                    } else {
+                        let handle = |req| http_websocket_classify(context, addr, req);
+                        let connection = http1_service
+                            .serve_connection(stream, service_fn(handle))
+                            .with_upgrades();
+                        futures::pin_mut!(connection);
+                        match futures::future::select(connection, context.shutdown.started()).await
+                        {
+                            Either::Left((res, _)) => res,
+                            Either::Right((_, mut connection)) => {
+                                connection.as_mut().graceful_shutdown();
+                                connection.await
+                            }
+                        }
                     };
-                    http2_service
-                        .serve_connection(stream, service_fn(handle))
-                        .await
-                } else {
-                    let handle = |req| http_websocket_classify(&context, addr, req);
-                    http1_service
-                        .serve_connection(stream, service_fn(handle))
-                        .with_upgrades()
-                        .await
-                };
-                if let Err(e) = res {
-                    println!("Error: {:?}", e);
+                    if let Err(e) = res {
+                        warn!(error = ?e, "Connection error");
+                    }
+                }
+                Err(e) => {
+                    warn!(error = ?e, "SSL handshake error");
                 }
-            }
-            Err(e) => {
-                println!("SSL handshake error: {:?}", e);
             }
         }
+        .instrument(tracing::info_span!("connection", %addr))
+        .await;
     });
 
-    futures::join!(server, statistics(start_time, context.map.clone()));
+    let ticker = statistics(start_time, context.map.clone(), alerts.clone());
+    futures::pin_mut!(ticker);
+    let ticker = futures::future::select(ticker, context.shutdown.started());
+    let timeout = Duration::from_secs(opt.shutdown_timeout.unwrap_or(10));
+    let orderly = async {
+        let captures = async {
+            context.shutdown.started().await;
+            info!(
+                "Shutting down, exiting in {} seconds at the latest",
+                timeout.as_secs()
+            );
+            let mut map = context.map.lock().await;
+            for s in map.values_mut() {
+                s.close();
+            }
+        };
+        futures::join!(server, ticker, captures);
+        // websockets are closed and the capture threads ended, their last
+        // flows are queued for export
+        context.shutdown.finished().await;
+        if let Some(exporter) = &context.exporter {
+            exporter.finish().await;
+        }
+        if let Some(alerts) = &alerts {
+            alerts.finish().await;
+        }
+    };
+    let deadline = async {
+        context.shutdown.started().await;
+        tokio::time::sleep(timeout).await;
+    };
+    futures::pin_mut!(orderly, deadline);
+    match futures::future::select(orderly, deadline).await {
+        Either::Left(_) => info!("Shut down"),
+        Either::Right(_) => warn!("Shutdown deadline passed, exiting anyway"),
+    }
 }
 
 async fn read_file(path: &Option<String>, expect: &str) -> Option<Vec<u8>> {
@@ -138,10 +368,64 @@ async fn read_file(path: &Option<String>, expect: &str) -> Option<Vec<u8>> {
     }
 }
 
+/// An address with an optional port, `port` by default.
+fn parse_address(s: &str, port: u16) -> Result<SocketAddr, std::net::AddrParseError> {
+    match s.parse::<std::net::IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, port)),
+        Err(_) => s.parse(),
+    }
+}
+
+/// A local socket path or an address, on port 514 by default.
+fn parse_syslog(s: &str) -> SyslogTarget {
+    if s.starts_with('/') {
+        SyslogTarget::Unix(s.into())
+    } else {
+        SyslogTarget::Udp(parse_address(s, 514).expect("Invalid syslog address"))
+    }
+}
+
+fn load_vendors(path: &Option<String>) -> Vendors {
+    if let Some(path) = path {
+        match Vendors::load(path) {
+            Ok(vendors) => return vendors,
+            Err(e) => warn!(
+                path,
+                error = ?e,
+                "Failed to load OUI file, fallback to embedded table"
+            ),
+        }
+    }
+    Vendors::embedded().unwrap_or_else(|e| {
+        error!(error = ?e, "Failed to read embedded OUI table");
+        Default::default()
+    })
+}
+
 #[derive(Clone)]
 pub struct AppContext {
     start_time: std::time::Instant,
     map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>,
+    capture_backend: Backend,
+    vendors: Arc<Vendors>,
+    reverse_dns: Option<Arc<ReverseDns>>,
+    exporter: Option<Exporter>,
+    alerts: Option<Arc<Alerts>>,
+    interface_events: broadcast::Sender<InterfaceEvent>,
+    privileges: Privileges,
+    shutdown: Shutdown,
+}
+
+impl AppContext {
+    /// The neighbor table of every interface, by interface name.
+    async fn neighbors_to_json(&self) -> Value {
+        let map = self.map.lock().await;
+        let mut m = serde_json::Map::with_capacity(map.len());
+        for (key, value) in map.iter() {
+            m.insert(key.clone(), value.neighbors_to_json(&self.vendors));
+        }
+        Value::Object(m)
+    }
 }
 
 #[derive(FromArgs)]
@@ -158,6 +442,106 @@ struct Options {
     /// use custom tls private key path (example: pem/test.key)
     #[argh(option, short = 'k')]
     private_key: Option<String>,
+
+    /// default capture backend (default: datalink, options: datalink, pcap, af_packet, pcap_file, counters)
+    #[argh(option, short = 'b')]
+    capture_backend: Option<Backend>,
+
+    /// load MAC vendor names from this IEEE oui.csv instead of the embedded table
+    #[argh(option)]
+    oui_file: Option<String>,
+
+    /// resolve names of unnamed addresses by PTR queries to this resolver (example: 192.168.1.1 or [::1]:53)
+    #[argh(option)]
+    reverse_dns: Option<String>,
+
+    /// reverse DNS queries per second at most (default: 10)
+    #[argh(option)]
+    reverse_dns_rate: Option<u32>,
+
+    /// export flows to this collector, repeatable (example: 192.168.1.10:2055)
+    #[argh(option)]
+    export: Vec<String>,
+
+    /// flow export protocol (default: ipfix, options: netflow9, ipfix)
+    #[argh(option)]
+    export_protocol: Option<ExportProtocol>,
+
+    /// seconds after which ongoing flows are exported and start over (default: 60)
+    #[argh(option)]
+    active_timeout: Option<u64>,
+
+    /// seconds without packets after which flows are exported (default: 15)
+    #[argh(option)]
+    inactive_timeout: Option<u64>,
+
+    /// seconds between retransmissions of the export templates (default: 60)
+    #[argh(option)]
+    template_refresh: Option<u64>,
+
+    /// receive sFlow, NetFlow and IPFIX on this address, repeatable (example: 0.0.0.0:6343)
+    #[argh(option)]
+    collect: Vec<String>,
+
+    /// JSON file with the alert rules to evaluate every second
+    #[argh(option)]
+    alert_rules: Option<String>,
+
+    /// POST alerts as JSON to this URL, repeatable
+    #[argh(option)]
+    alert_webhook: Vec<String>,
+
+    /// send alerts to this syslog address or local socket (example: /dev/log)
+    #[argh(option)]
+    alert_syslog: Option<String>,
+
+    /// mail alerts through this SMTP relay (example: 127.0.0.1:25)
+    #[argh(option)]
+    alert_smtp: Option<String>,
+
+    /// sender of alert mails (default: network_view@localhost)
+    #[argh(option)]
+    alert_mail_from: Option<String>,
+
+    /// recipient of alert mails, repeatable
+    #[argh(option)]
+    alert_mail_to: Vec<String>,
+
+    /// after binding the listen address give up every privilege except CAP_NET_RAW and CAP_NET_ADMIN (linux only)
+    #[argh(switch)]
+    drop_privileges: bool,
+
+    /// leave opening capture sockets to a privileged helper process and run the server as this user (linux only)
+    #[argh(option)]
+    user: Option<String>,
+
+    /// refuse system calls the server never needs, such as execve, ptrace and mount (linux x86_64 and aarch64)
+    #[argh(switch)]
+    seccomp: bool,
+
+    /// seconds to wind down after SIGINT or SIGTERM before exiting anyway (default: 10)
+    #[argh(option)]
+    shutdown_timeout: Option<u64>,
+
+    /// log level or filter directives, RUST_LOG when missing (default: info, example: network_view=debug)
+    #[argh(option)]
+    log_level: Option<String>,
+
+    /// log line format (default: text, options: text, json)
+    #[argh(option)]
+    log_format: Option<LogFormat>,
+
+    /// send logs to journald instead of stdout (linux only)
+    #[argh(switch)]
+    log_journald: bool,
+
+    /// send logs to this syslog address or local socket instead of stdout (example: /dev/log)
+    #[argh(option)]
+    log_syslog: Option<String>,
+
+    /// measure capture pipeline throughput on generated frames and exit
+    #[argh(switch)]
+    benchmark: bool,
 }
 
 #[derive(Clone)]
@@ -192,7 +576,7 @@ async fn http_websocket_classify(
                         .get(header::CONNECTION)
                         .and_then(|h| h.to_str().ok())
                         .map(|h| {
-                            h.split(|c| c == ' ' || c == ',')
+                            h.split([' ', ','])
                                 .any(|p| p.eq_ignore_ascii_case("Upgrade"))
                         })
                         .unwrap_or(false)
@@ -214,21 +598,24 @@ async fn http_websocket_classify(
                     headers.append(header::CONNECTION, UPGRADE_HEADER_VALUE);
                     headers.append(header::UPGRADE, WEBSOCKET_HEADER_VALUE);
                     headers.append(header::SEC_WEBSOCKET_ACCEPT, derived);
-                    tokio::spawn(upgrade_web_socket(context.to_owned(), addr, req));
+                    // within the connection span, which has the address
+                    let websocket = upgrade_web_socket(context.to_owned(), req)
+                        .instrument(tracing::info_span!("websocket"));
+                    context.shutdown.spawn(websocket);
                     return Ok(res);
                 } else {
-                    println!( "Connection ({}) come with SEC_WEBSOCKET_KEY but can't upgrade to websocket and fallback to normal http handle. ",&addr);
+                    debug!("Request come with SEC_WEBSOCKET_KEY but can't upgrade to websocket and fallback to normal http handle");
                 }
             }
             Err(err) => {
-                println!("Error derive_accept_key: {}. ", err);
+                warn!(error = %err, "Error derive_accept_key");
             }
         }
     }
     return on_http(context, addr, req).await;
 }
 
-async fn upgrade_web_socket(context: AppContext, addr: SocketAddr, mut req: Request<Incoming>) {
+async fn upgrade_web_socket(context: AppContext, mut req: Request<Incoming>) {
     match hyper::upgrade::on(&mut req).await {
         Ok(upgraded) => {
             let upgraded = TokioIo::new(upgraded);
@@ -238,12 +625,12 @@ async fn upgrade_web_socket(context: AppContext, addr: SocketAddr, mut req: Requ
                 None,
             )
             .await;
-            println!("Websocket({}) connected", addr);
+            info!("Websocket connected");
             let _ = on_websocket(&context, req, ws_stream).await;
-            println!("Websocket({}) disconnected", addr);
+            info!("Websocket disconnected");
         }
         Err(e) => {
-            println!("Websocket upgrade error: {}", e);
+            warn!(error = %e, "Websocket upgrade error");
         }
     }
 }
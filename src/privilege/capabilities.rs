@@ -0,0 +1,95 @@
+use std::io;
+
+pub const CAP_NET_ADMIN: u32 = 12;
+pub const CAP_NET_RAW: u32 = 13;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct Header {
+    version: u32,
+    pid: libc::c_int,
+}
+
+/// One half of the 64 bit capability sets.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Data {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn header() -> Header {
+    Header {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    }
+}
+
+fn get() -> io::Result<[Data; 2]> {
+    let mut header = header();
+    let mut data = [Data::default(); 2];
+    let res = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(data)
+}
+
+fn prctl(option: libc::c_int, arg: libc::c_ulong) -> io::Result<()> {
+    if unsafe { libc::prctl(option, arg, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The effective capabilities of the calling thread, bit `n` for
+/// capability `n`.
+pub fn effective() -> io::Result<u64> {
+    let data = get()?;
+    Ok(data[0].effective as u64 | (data[1].effective as u64) << 32)
+}
+
+/// Give up every capability but those of `keep` the thread has, for good:
+/// they leave the bounding and the ambient set, and no new privileges can
+/// be gained through executables. Threads started afterwards inherit the
+/// reduced sets, the ones already running keep theirs.
+pub fn keep_only(keep: u64) -> io::Result<()> {
+    let last = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(63);
+    for cap in 0..=last.min(63) {
+        if keep & 1 << cap != 0 {
+            continue;
+        }
+        match prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong) {
+            Ok(()) => {}
+            // shrinking the bounding set takes CAP_SETPCAP, which only root
+            // has; without it no new privileges below keeps them out anyway
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => break,
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    // the ambient set is younger than some kernels still around
+    let _ = prctl(
+        libc::PR_CAP_AMBIENT,
+        libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+    );
+
+    let mut data = get()?;
+    for (half, data) in data.iter_mut().enumerate() {
+        let keep = (keep >> (32 * half)) as u32 & data.permitted;
+        data.permitted = keep;
+        data.effective = keep;
+        data.inheritable = 0;
+    }
+    let mut header = header();
+    let res = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    prctl(libc::PR_SET_NO_NEW_PRIVS, 1)
+}
@@ -0,0 +1,207 @@
+//! The privileged half of privilege separation: a process forked at startup
+//! which keeps `CAP_NET_RAW` and does nothing but create packet sockets for
+//! the main process, handing them over a socket pair.
+
+use tracing::{error, info};
+
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, OnceLock};
+
+use super::capabilities;
+
+/// The main process's end of the socket pair, once the helper runs.
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+
+const REQUEST_PACKET_SOCKET: u8 = 1;
+
+/// Room for one `SCM_RIGHTS` message with one descriptor, aligned like
+/// `cmsghdr`.
+type Control = [u64; 4];
+
+/// Forks the helper. Has to run before any other thread is started and
+/// before anything is opened the helper should not hold on to.
+pub fn start() -> io::Result<()> {
+    let mut fds = [0; 2];
+    let res = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (main, helper) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(main);
+            serve(helper)
+        }
+        pid => {
+            drop(helper);
+            info!(pid, "Capture helper started");
+            let _ = HELPER.set(Mutex::new(main));
+            Ok(())
+        }
+    }
+}
+
+pub fn is_running() -> bool {
+    HELPER.get().is_some()
+}
+
+/// A raw packet socket for no protocol, which receives nothing until it is
+/// bound to an interface along with the protocol, so no frame of another
+/// interface gets into its ring. From the helper when it runs, the process
+/// opens it itself otherwise.
+pub fn packet_socket() -> io::Result<OwnedFd> {
+    match HELPER.get() {
+        Some(helper) => request(&helper.lock().unwrap()),
+        None => open_packet_socket(),
+    }
+}
+
+/// Whether packet sockets can be opened, by opening the very socket a
+/// capture starts with and closing it again. Nothing is queued on it.
+pub fn probe_packet_socket() -> io::Result<()> {
+    packet_socket().map(drop)
+}
+
+fn open_packet_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn serve(socket: OwnedFd) -> ! {
+    // the helper has no business outliving the main process
+    unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) };
+    if let Err(e) = capabilities::keep_only(1 << capabilities::CAP_NET_RAW) {
+        error!(error = ?e, "Capture helper failed to drop privileges");
+        std::process::exit(1);
+    }
+    let mut request = [0u8; 1];
+    loop {
+        let len = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                request.as_mut_ptr() as *mut libc::c_void,
+                request.len(),
+                0,
+            )
+        };
+        match len {
+            // the main process is gone
+            0 => std::process::exit(0),
+            len if len < 0 => {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                std::process::exit(1);
+            }
+            _ => {}
+        }
+        let res = match request[0] {
+            REQUEST_PACKET_SOCKET => open_packet_socket(),
+            _ => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        };
+        if reply(socket.as_raw_fd(), res).is_err() {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Sends the errno, 0 on success, along with the descriptor.
+fn reply(socket: RawFd, res: io::Result<OwnedFd>) -> io::Result<()> {
+    let (errno, fd) = match &res {
+        Ok(fd) => (0, Some(fd.as_raw_fd())),
+        Err(e) => (e.raw_os_error().unwrap_or(libc::EIO), None),
+    };
+    let mut payload = errno.to_ne_bytes();
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let mut control: Control = [0; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        let len = mem::size_of::<RawFd>() as u32;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(len) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        }
+    }
+    if unsafe { libc::sendmsg(socket, &msg, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn request(socket: &OwnedFd) -> io::Result<OwnedFd> {
+    let request = [REQUEST_PACKET_SOCKET];
+    let res = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut payload = [0u8; 4];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let mut control: Control = [0; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of::<Control>() as _;
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if len as usize != payload.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "capture helper exited",
+        ));
+    }
+    let errno = i32::from_ne_bytes(payload);
+    if errno != 0 {
+        return Err(io::Error::from_raw_os_error(errno));
+    }
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no descriptor from the capture helper",
+            ));
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd);
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
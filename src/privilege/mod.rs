@@ -0,0 +1,184 @@
+//! The privileges live captures need, telling whether the process has them
+//! and giving up all the others, up to leaving them to a separate helper
+//! process.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+mod capabilities;
+#[cfg(target_os = "linux")]
+mod helper;
+#[cfg(target_os = "linux")]
+mod seccomp;
+
+#[cfg(target_os = "linux")]
+pub use helper::{packet_socket, probe_packet_socket};
+
+/// How to grant a live capture what it needs, shown along with permission
+/// errors.
+pub const HINT: &str = "run as root, grant the capabilities with `setcap cap_net_raw,cap_net_admin=eip network_view`, or use the counters backend";
+
+#[derive(Clone, Copy, Serialize)]
+pub struct Privileges {
+    pub root: bool,
+    /// Opening packet sockets, required by every live capture backend.
+    pub net_raw: bool,
+    /// Promiscuous mode and changing interface settings.
+    pub net_admin: bool,
+    /// All other privileges were given up at startup.
+    pub dropped: bool,
+    /// Live captures are opened by the privileged helper process, this one
+    /// runs as an unprivileged user.
+    pub helper: bool,
+    /// System calls the server never needs are filtered out.
+    pub seccomp: bool,
+}
+
+impl Privileges {
+    pub fn detect() -> Self {
+        #[cfg(unix)]
+        let root = unsafe { libc::geteuid() } == 0;
+        #[cfg(not(unix))]
+        let root = false;
+        #[cfg(target_os = "linux")]
+        let (net_raw, net_admin) = match capabilities::effective() {
+            Ok(effective) => (
+                effective & 1 << capabilities::CAP_NET_RAW != 0,
+                effective & 1 << capabilities::CAP_NET_ADMIN != 0,
+            ),
+            Err(_) => (root, root),
+        };
+        // elsewhere capture devices belong to root, unless the
+        // administrator opened them up
+        #[cfg(not(target_os = "linux"))]
+        let (net_raw, net_admin) = (root, root);
+        Self {
+            root,
+            net_raw,
+            net_admin,
+            dropped: false,
+            #[cfg(target_os = "linux")]
+            helper: helper::is_running(),
+            #[cfg(not(target_os = "linux"))]
+            helper: false,
+            seccomp: false,
+        }
+    }
+
+    /// What is missing for live captures, printed at startup.
+    pub fn missing(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.helper {
+            return missing;
+        }
+        if !self.net_raw {
+            missing.push("CAP_NET_RAW");
+        }
+        if !self.net_admin {
+            missing.push("CAP_NET_ADMIN");
+        }
+        missing
+    }
+
+    /// The `listen_interfaces` response when capturing is not permitted.
+    pub fn permission_denied(&self, name: &str) -> Value {
+        let hint = if self.helper {
+            "the capture helper only opens sockets for the af_packet backend"
+        } else {
+            HINT
+        };
+        json!({
+            "error": {
+                "kind": "permission_denied",
+                "interface": name,
+                "message": "this process may not capture packets",
+                "missing": self.missing(),
+                "privileges": self,
+                "hint": hint,
+            }
+        })
+    }
+}
+
+/// Give up every privilege but capturing: only `CAP_NET_RAW` and
+/// `CAP_NET_ADMIN` are kept, as far as the process has them. Has to run
+/// before any other thread is started, capabilities are per thread.
+pub fn drop_privileges() -> io::Result<Privileges> {
+    #[cfg(target_os = "linux")]
+    {
+        capabilities::keep_only(1 << capabilities::CAP_NET_RAW | 1 << capabilities::CAP_NET_ADMIN)?;
+        Ok(Privileges {
+            dropped: true,
+            ..Privileges::detect()
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "dropping privileges is only supported on linux",
+    ))
+}
+
+/// Fork the capture helper, which keeps `CAP_NET_RAW` to open packet
+/// sockets. Has to run before any other thread is started or any file or
+/// socket is opened.
+pub fn start_helper() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return helper::start();
+    #[cfg(not(target_os = "linux"))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the capture helper is only available on linux",
+    ))
+}
+
+/// Become `user` for good, with its primary group and no supplementary
+/// groups. Taking a user other than root clears every capability.
+#[cfg(unix)]
+pub fn switch_user(user: &str) -> io::Result<()> {
+    let name = std::ffi::CString::new(user)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid user name"))?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such user"));
+    }
+    let (uid, gid) = unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) };
+    unsafe {
+        if libc::setgroups(0, std::ptr::null()) < 0
+            || libc::setgid(gid) < 0
+            || libc::setuid(uid) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // a way back would mean the switch did not take
+        if uid != 0 && libc::setuid(0) == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "root privileges could be regained",
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn switch_user(_: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "switching user is only available on unix",
+    ))
+}
+
+/// Refuse the system calls the server never needs, see `seccomp::DENIED`.
+pub fn apply_seccomp() -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return seccomp::apply();
+    #[cfg(not(target_os = "linux"))]
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "seccomp is only available on linux",
+    ))
+}
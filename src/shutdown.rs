@@ -0,0 +1,105 @@
+//! Orderly shutdown on SIGINT or SIGTERM: the server stops accepting,
+//! websockets are closed, captures end and queued exports and alerts go
+//! out, all within the deadline given by `--shutdown-timeout`.
+
+use futures::channel::oneshot;
+use futures::future::Shared;
+use futures::{Future, FutureExt};
+use tokio::sync::watch;
+use tracing::warn;
+
+use std::sync::Arc;
+
+/// Cloned into everything which has to wind down: tells when shutting down
+/// started and counts the tasks still to finish.
+#[derive(Clone)]
+pub struct Shutdown {
+    signal: Shared<oneshot::Receiver<()>>,
+    running: Arc<watch::Sender<usize>>,
+}
+
+impl Shutdown {
+    /// Shutting down starts once the sender is dropped.
+    pub fn new() -> (oneshot::Sender<()>, Self) {
+        let (tx, rx) = oneshot::channel();
+        let shutdown = Self {
+            signal: rx.shared(),
+            running: Arc::new(watch::channel(0).0),
+        };
+        (tx, shutdown)
+    }
+
+    /// Resolves once shutting down started.
+    pub fn started(&self) -> impl Future<Output = ()> {
+        self.signal.clone().map(|_| ())
+    }
+
+    pub fn has_started(&self) -> bool {
+        self.signal.clone().now_or_never().is_some()
+    }
+
+    /// Spawns a task `finished` waits for. Nothing new is started once
+    /// shutting down, the task is dropped.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if self.has_started() {
+            return;
+        }
+        self.running.send_modify(|running| *running += 1);
+        let running = Running(self.running.clone());
+        tokio::spawn(async move {
+            task.await;
+            drop(running);
+        });
+    }
+
+    /// Resolves once every task from `spawn` is done.
+    pub async fn finished(&self) {
+        let mut running = self.running.subscribe();
+        let _ = running.wait_for(|running| *running == 0).await;
+    }
+}
+
+/// Counts a task from `Shutdown::spawn` until it is done or dropped along
+/// with the runtime.
+struct Running(Arc<watch::Sender<usize>>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.send_modify(|running| *running -= 1);
+    }
+}
+
+/// Waits for SIGINT or SIGTERM.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                warn!(error = ?e, "Failed to listen for SIGTERM");
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        futures::select! {
+            _ = tokio::signal::ctrl_c().fuse() => {}
+            _ = terminate.recv().fuse() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Starts shutting down on the first signal and exits right away on the
+/// second one.
+pub async fn on_signal(tx: oneshot::Sender<()>) {
+    signal().await;
+    drop(tx);
+    signal().await;
+    warn!("Second signal, exiting immediately");
+    std::process::exit(1);
+}
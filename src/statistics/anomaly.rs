@@ -0,0 +1,461 @@
+use pnet::util::MacAddr;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::flow::{Segment, IP_PROTOCOL_TCP};
+use super::header::PackageHeader;
+
+const SYN: u8 = 0x02;
+const ACK: u8 = 0x10;
+
+/// Weight of a new tick in a baseline once it has seen `1 / ALPHA` ticks,
+/// about an hour's worth: every hour of the day has a baseline of its own,
+/// so a bucket forgets over a few days.
+const ALPHA: f64 = 1.0 / 3600.0;
+/// Ticks a baseline needs before deviations from it are flagged.
+const WARMUP: u64 = 300;
+/// Standard deviations from the baseline which count as an anomaly.
+const THRESHOLD: f64 = 4.0;
+/// A spike or drop also has to change the rate by half, so that nearly
+/// constant traffic does not flag every small wobble.
+const MIN_CHANGE: f64 = 0.5;
+/// Broadcast bytes per second below which there is no storm.
+const MIN_STORM_RATE: f64 = 100_000.0;
+/// Seconds the protocols of a new interface are learned without events.
+const LEARN_PROTOCOLS: u64 = 300;
+/// TCP connection attempts to this many distinct ports within one second
+/// make a port scan.
+const PORT_SCAN_PORTS: usize = 100;
+/// Seconds before the same source is reported scanning again.
+const PORT_SCAN_REPEAT: u64 = 60;
+/// Sources and ports per source the capture thread collects per flush.
+const MAX_SOURCES: usize = 4096;
+const MAX_PORTS: usize = 1024;
+/// Events kept for queries per interface.
+const MAX_EVENTS: usize = 1024;
+
+/// Destination ports of the TCP connection attempts per source.
+pub type Attempts = HashMap<IpAddr, HashSet<u16>>;
+
+/// Record a TCP connection attempt, for port scan detection.
+pub fn observe(segment: &Segment, attempts: &mut Attempts) {
+    if segment.key.protocol != IP_PROTOCOL_TCP || segment.tcp[13] & (SYN | ACK) != SYN {
+        return;
+    }
+    let source = segment.source.ip();
+    if !attempts.contains_key(&source) && attempts.len() >= MAX_SOURCES {
+        return;
+    }
+    let ports = attempts.entry(source).or_default();
+    if ports.len() < MAX_PORTS {
+        ports.insert(segment.destination.port());
+    }
+}
+
+/// Exponentially weighted mean and variance of one value.
+#[derive(Clone, Copy, Default)]
+struct Baseline {
+    mean: f64,
+    variance: f64,
+    samples: u64,
+}
+
+impl Baseline {
+    fn update(&mut self, value: f64) {
+        self.samples += 1;
+        // a plain running average until the weights catch up
+        let alpha = ALPHA.max(1.0 / self.samples as f64);
+        let diff = value - self.mean;
+        let increment = alpha * diff;
+        self.mean += increment;
+        self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+    }
+
+    /// Standard deviations `value` is away from the mean, `None` while
+    /// warming up.
+    fn deviation(&self, value: f64) -> Option<f64> {
+        if self.samples < WARMUP {
+            return None;
+        }
+        // keeps perfectly constant traffic from dividing by zero
+        let deviation = self.variance.sqrt().max(1.0);
+        Some((value - self.mean) / deviation)
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// Bytes per second far above the baseline of the hour.
+    RateSpike {
+        value: f64,
+        mean: f64,
+        deviation: f64,
+    },
+    /// Bytes per second far below the baseline of the hour.
+    RateDrop {
+        value: f64,
+        mean: f64,
+        deviation: f64,
+    },
+    /// Broadcast bytes per second far above their baseline.
+    BroadcastStorm {
+        value: f64,
+        mean: f64,
+        deviation: f64,
+    },
+    /// An EtherType, or IP protocol, the interface had not carried before.
+    NewProtocol {
+        protocol: u16,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ip_protocol: Option<u8>,
+    },
+    /// TCP connection attempts to many distinct ports from one address.
+    PortScan { source: IpAddr, ports: usize },
+}
+
+#[derive(Clone, Serialize)]
+pub struct Event {
+    /// Milliseconds since start, like the history timestamps.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub anomaly: Anomaly,
+}
+
+/// Baselines of one interface, learned from its history one tick at a
+/// time. Every hour of the day (UTC) has baselines of its own, so traffic
+/// which is high every afternoon is not flagged every afternoon.
+pub struct AnomalyDetector {
+    since: u64,
+    rate: [Baseline; 24],
+    broadcast: [Baseline; 24],
+    /// Rate and broadcast deviations in progress, reported once each. The
+    /// baselines keep learning meanwhile, a lasting change becomes normal
+    /// after a while.
+    deviating: (bool, bool),
+    protocols: HashSet<(u16, Option<u8>)>,
+    scanners: HashMap<IpAddr, u64>,
+    events: VecDeque<Event>,
+}
+
+impl AnomalyDetector {
+    pub fn new(timestamp: u64) -> Self {
+        Self {
+            since: timestamp,
+            rate: [Baseline::default(); 24],
+            broadcast: [Baseline::default(); 24],
+            deviating: (false, false),
+            protocols: HashSet::new(),
+            scanners: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Look at the newest history entry, one second of traffic.
+    pub fn update(
+        &mut self,
+        timestamp: u64,
+        headers: &HashMap<PackageHeader, usize>,
+        attempts: &Attempts,
+    ) {
+        let hour = (SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 3600
+            % 24) as usize;
+        self.update_hour(hour, timestamp, headers, attempts);
+    }
+
+    fn update_hour(
+        &mut self,
+        hour: usize,
+        timestamp: u64,
+        headers: &HashMap<PackageHeader, usize>,
+        attempts: &Attempts,
+    ) {
+        let mut anomalies = Vec::new();
+
+        let mut rate = 0.0;
+        let mut broadcast = 0.0;
+        let learning = timestamp < self.since + LEARN_PROTOCOLS * 1000;
+        for (header, &len) in headers {
+            rate += len as f64;
+            if header.destination == Some(MacAddr::broadcast()) {
+                broadcast += len as f64;
+            }
+            let protocol = (
+                header.protocol,
+                header.ip_header.as_ref().map(|h| h.protocol),
+            );
+            if self.protocols.insert(protocol) && !learning {
+                anomalies.push(Anomaly::NewProtocol {
+                    protocol: protocol.0,
+                    ip_protocol: protocol.1,
+                });
+            }
+        }
+
+        let baseline = &mut self.rate[hour];
+        let deviation = baseline.deviation(rate);
+        let mean = baseline.mean;
+        let changed = (rate - mean).abs() > mean * MIN_CHANGE;
+        let deviating = deviation.is_some_and(|d| d.abs() > THRESHOLD) && changed;
+        if deviating && !self.deviating.0 {
+            let deviation = deviation.unwrap();
+            anomalies.push(if deviation > 0.0 {
+                Anomaly::RateSpike {
+                    value: rate,
+                    mean,
+                    deviation,
+                }
+            } else {
+                Anomaly::RateDrop {
+                    value: rate,
+                    mean,
+                    deviation,
+                }
+            });
+        }
+        self.deviating.0 = deviating;
+        baseline.update(rate);
+
+        let baseline = &mut self.broadcast[hour];
+        let deviation = baseline.deviation(broadcast);
+        let storm = deviation.is_some_and(|d| d > THRESHOLD) && broadcast > MIN_STORM_RATE;
+        if storm && !self.deviating.1 {
+            anomalies.push(Anomaly::BroadcastStorm {
+                value: broadcast,
+                mean: baseline.mean,
+                deviation: deviation.unwrap(),
+            });
+        }
+        self.deviating.1 = storm;
+        baseline.update(broadcast);
+
+        self.scanners
+            .retain(|_, reported| timestamp < *reported + PORT_SCAN_REPEAT * 1000);
+        for (source, ports) in attempts {
+            if ports.len() >= PORT_SCAN_PORTS && !self.scanners.contains_key(source) {
+                self.scanners.insert(*source, timestamp);
+                anomalies.push(Anomaly::PortScan {
+                    source: *source,
+                    ports: ports.len(),
+                });
+            }
+        }
+
+        for anomaly in anomalies {
+            self.events.push_back(Event { timestamp, anomaly });
+        }
+        while self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::header::IpHeader;
+    use std::net::Ipv4Addr;
+
+    const HOUR: usize = 12;
+    const IPV4: u16 = 0x0800;
+    const ARP: u16 = 0x0806;
+    const UDP: u8 = 17;
+    const ICMP: u8 = 1;
+
+    fn header(protocol: u16, ip_protocol: Option<u8>, broadcast: bool) -> PackageHeader {
+        let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        PackageHeader {
+            protocol,
+            source: Some(MacAddr::new(2, 0, 0, 0, 0, 1)),
+            destination: Some(match broadcast {
+                true => MacAddr::broadcast(),
+                false => MacAddr::new(2, 0, 0, 0, 0, 2),
+            }),
+            ip_header: ip_protocol.map(|protocol| IpHeader {
+                source,
+                destination: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                protocol,
+                fragment: false,
+            }),
+            vlan: None,
+            inner_vlan: None,
+            mpls_label: None,
+            tunnel: None,
+            host: None,
+        }
+    }
+
+    /// One tick of `bytes` TCP traffic, to the broadcast address if asked.
+    fn tcp(bytes: usize, broadcast: bool) -> HashMap<PackageHeader, usize> {
+        HashMap::from([(header(IPV4, Some(IP_PROTOCOL_TCP), broadcast), bytes)])
+    }
+
+    /// Feeds ticks one second apart, starting at second `start`.
+    fn feed(
+        detector: &mut AnomalyDetector,
+        start: u64,
+        ticks: impl IntoIterator<Item = HashMap<PackageHeader, usize>>,
+    ) -> u64 {
+        let mut second = start;
+        for headers in ticks {
+            detector.update_hour(HOUR, second * 1000, &headers, &Attempts::new());
+            second += 1;
+        }
+        second
+    }
+
+    /// A warmed up detector, which saw 1000 and 1200 bytes every other
+    /// second. Returns the next second.
+    fn warmed_up(detector: &mut AnomalyDetector, broadcast: bool) -> u64 {
+        let ticks = (0..WARMUP).map(|i| tcp(1000 + (i % 2) as usize * 200, broadcast));
+        feed(detector, 0, ticks)
+    }
+
+    fn kinds(detector: &AnomalyDetector) -> Vec<String> {
+        detector
+            .events
+            .iter()
+            .map(|event| {
+                let json = serde_json::to_value(event).unwrap();
+                json["kind"].as_str().unwrap().to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn baseline_warmup() {
+        let mut baseline = Baseline::default();
+        for _ in 1..WARMUP {
+            baseline.update(1000.0);
+        }
+        assert!(baseline.deviation(1000.0).is_none());
+        // a running average while the samples are few
+        assert_eq!(baseline.mean, 1000.0);
+        baseline.update(1000.0);
+        // constant traffic deviates by at least one
+        assert_eq!(baseline.deviation(1010.0), Some(10.0));
+    }
+
+    #[test]
+    fn no_events_while_warming_up() {
+        let mut detector = AnomalyDetector::new(0);
+        let second = feed(&mut detector, 0, (0..WARMUP - 1).map(|_| tcp(1000, false)));
+        feed(
+            &mut detector,
+            second,
+            [tcp(1_000_000, false), HashMap::new()],
+        );
+        assert!(kinds(&detector).is_empty());
+    }
+
+    #[test]
+    fn rate_spike_with_hysteresis() {
+        let mut detector = AnomalyDetector::new(0);
+        let mut second = warmed_up(&mut detector, false);
+        // within the threshold
+        second = feed(&mut detector, second, [tcp(1300, false)]);
+        assert!(kinds(&detector).is_empty());
+
+        // reported once while it lasts
+        let spike = (0..3).map(|_| tcp(100_000, false));
+        second = feed(&mut detector, second, spike);
+        assert_eq!(kinds(&detector), ["rate_spike"]);
+
+        // and again after the rate went back to normal
+        second = feed(&mut detector, second, [tcp(1100, false)]);
+        feed(&mut detector, second, [tcp(100_000, false)]);
+        assert_eq!(kinds(&detector), ["rate_spike", "rate_spike"]);
+    }
+
+    #[test]
+    fn rate_drop() {
+        let mut detector = AnomalyDetector::new(0);
+        let second = warmed_up(&mut detector, false);
+        feed(&mut detector, second, [HashMap::new(), HashMap::new()]);
+        assert_eq!(kinds(&detector), ["rate_drop"]);
+    }
+
+    #[test]
+    fn broadcast_storm() {
+        let mut detector = AnomalyDetector::new(0);
+        let mut second = warmed_up(&mut detector, true);
+        // far above the baseline but below the storm rate
+        second = feed(&mut detector, second, [tcp(50_000, true)]);
+        assert_eq!(kinds(&detector), ["rate_spike"]);
+        second = feed(&mut detector, second, [tcp(1100, true)]);
+        feed(&mut detector, second, [tcp(1_000_000, true)]);
+        assert_eq!(
+            kinds(&detector),
+            ["rate_spike", "rate_spike", "broadcast_storm"]
+        );
+    }
+
+    #[test]
+    fn new_protocols_after_learning() {
+        let mut detector = AnomalyDetector::new(0);
+        let udp = HashMap::from([(header(IPV4, Some(UDP), false), 100)]);
+        feed(&mut detector, 0, [tcp(100, false), udp]);
+        assert!(kinds(&detector).is_empty());
+
+        let headers = HashMap::from([
+            (header(IPV4, Some(IP_PROTOCOL_TCP), false), 100),
+            (header(IPV4, Some(UDP), false), 100),
+            (header(ARP, None, true), 100),
+        ]);
+        let second = feed(&mut detector, LEARN_PROTOCOLS, [headers]);
+        assert_eq!(kinds(&detector), ["new_protocol"]);
+        let icmp = || HashMap::from([(header(IPV4, Some(ICMP), false), 100)]);
+        feed(&mut detector, second, [icmp(), icmp()]);
+        assert_eq!(kinds(&detector), ["new_protocol", "new_protocol"]);
+        let json = detector.to_json();
+        assert_eq!(json[0]["protocol"], ARP);
+        assert!(json[0].get("ip_protocol").is_none());
+        assert_eq!(json[1]["ip_protocol"], ICMP);
+    }
+
+    #[test]
+    fn port_scans() {
+        let scanner = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let scan = |ports: u16| Attempts::from([(scanner, (1..=ports).collect())]);
+        let mut detector = AnomalyDetector::new(0);
+        let mut tick = |second: u64, attempts: &Attempts| {
+            detector.update_hour(HOUR, second * 1000, &HashMap::new(), attempts);
+            kinds(&detector).len()
+        };
+        assert_eq!(tick(0, &scan(PORT_SCAN_PORTS as u16 - 1)), 0);
+        assert_eq!(tick(1, &scan(PORT_SCAN_PORTS as u16)), 1);
+        // the same source is not reported again right away
+        assert_eq!(tick(2, &scan(PORT_SCAN_PORTS as u16)), 1);
+        assert_eq!(tick(1 + PORT_SCAN_REPEAT, &scan(PORT_SCAN_PORTS as u16)), 2);
+    }
+
+    #[test]
+    fn connection_attempts() {
+        let ip_header = header(IPV4, Some(IP_PROTOCOL_TCP), false)
+            .ip_header
+            .unwrap();
+        let mut attempts = Attempts::new();
+        for (port, flags) in [(80u16, SYN), (443, SYN | ACK), (22, ACK), (8080, SYN)] {
+            let mut tcp = [0u8; 20];
+            tcp[..2].copy_from_slice(&40000u16.to_be_bytes());
+            tcp[2..4].copy_from_slice(&port.to_be_bytes());
+            tcp[12] = 5 << 4;
+            tcp[13] = flags;
+            observe(&Segment::new(&ip_header, &tcp).unwrap(), &mut attempts);
+        }
+        let ports = &attempts[&ip_header.source];
+        assert!(ports.len() == 2 && ports.contains(&80) && ports.contains(&8080));
+    }
+}
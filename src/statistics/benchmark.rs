@@ -0,0 +1,146 @@
+//! `network_view --benchmark`: feeds generated frames through the capture
+//! pipeline and through the original per packet design, and reports the
+//! sustained packets per second of each.
+
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use futures::{SinkExt, StreamExt};
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{capture_loop, PackageHeader, ParseOptions, CHANNEL_CAPACITY};
+use crate::capture::{CaptureSource, FrameInfo, LinkType};
+
+const DURATION: Duration = Duration::from_secs(5);
+const FRAME_LEN: usize = 64;
+/// Frames per batch, roughly what one ring block holds on a busy link.
+const BATCH: usize = 256;
+
+/// Endless stream of UDP over IPv4 frames from 256 hosts.
+struct SyntheticSource {
+    frames: Vec<Vec<u8>>,
+    position: usize,
+}
+
+impl SyntheticSource {
+    fn new() -> Self {
+        let frames = (0..=255u8)
+            .map(|host| {
+                let mut frame = vec![0u8; FRAME_LEN];
+                frame[0..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+                frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 1, host]);
+                frame[12..14].copy_from_slice(&[0x08, 0x00]);
+                let ip = &mut frame[14..34];
+                ip[0] = 0x45;
+                ip[2..4].copy_from_slice(&((FRAME_LEN - 14) as u16).to_be_bytes());
+                ip[8] = 64;
+                ip[9] = 17;
+                ip[12..16].copy_from_slice(&[10, 0, 1, host]);
+                ip[16..20].copy_from_slice(&[10, 0, 0, 1]);
+                frame
+            })
+            .collect();
+        Self {
+            frames,
+            position: 0,
+        }
+    }
+}
+
+impl CaptureSource for SyntheticSource {
+    fn next(&mut self) -> io::Result<Option<&[u8]>> {
+        let position = self.position;
+        self.position = (position + 1) % self.frames.len();
+        Ok(Some(&self.frames[position]))
+    }
+
+    fn next_batch(&mut self, handle: &mut dyn FnMut(&[u8], FrameInfo)) -> io::Result<()> {
+        for _ in 0..BATCH {
+            let frame = &self.frames[self.position];
+            handle(frame, FrameInfo::of(frame));
+            self.position = (self.position + 1) % self.frames.len();
+        }
+        Ok(())
+    }
+
+    fn link_type(&self) -> LinkType {
+        LinkType::Ethernet
+    }
+}
+
+pub async fn benchmark() {
+    println!("benchmark {} seconds each", DURATION.as_secs());
+    let per_packet = per_packet().await;
+    println!("per packet channel:  {:>12.0} packets/s", per_packet);
+    let batched = batched().await;
+    println!(
+        "batched aggregation: {:>12.0} packets/s ({:.1}x)",
+        batched,
+        batched / per_packet
+    );
+}
+
+/// The design before batching: one channel message and one lock per packet.
+async fn per_packet() -> f64 {
+    let buffer: Arc<Mutex<HashMap<PackageHeader, usize>>> = Default::default();
+    let (mut tx, mut rx) = mpsc::channel(64);
+    tokio::task::spawn_blocking(move || {
+        use futures::executor::block_on;
+        let mut source = SyntheticSource::new();
+        let options = ParseOptions {
+            link_type: source.link_type(),
+            decapsulate: false,
+        };
+        while let Ok(Some(package)) = source.next() {
+            let header = match PackageHeader::new(&options, package) {
+                Some(h) => h,
+                None => continue,
+            };
+            if block_on(tx.send((header, package.len()))).is_err() {
+                break;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    let mut packets = 0u64;
+    while start.elapsed() < DURATION {
+        if let Some((header, len)) = rx.next().await {
+            let mut buffer = buffer.lock().await;
+            *buffer.entry(header).or_insert(0) += len;
+            packets += 1;
+        }
+    }
+    packets as f64 / start.elapsed().as_secs_f64()
+}
+
+async fn batched() -> f64 {
+    let mut buffer: HashMap<PackageHeader, usize> = HashMap::new();
+    let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::task::spawn_blocking(move || {
+        capture_loop(
+            Box::new(SyntheticSource::new()),
+            tx,
+            &Default::default(),
+            &Default::default(),
+            &[],
+            Instant::now(),
+            None,
+        )
+    });
+
+    let start = Instant::now();
+    let mut bytes = 0usize;
+    while start.elapsed() < DURATION {
+        if let Some(aggregation) = rx.next().await {
+            for (header, len) in aggregation.headers {
+                bytes += len;
+                *buffer.entry(header).or_insert(0) += len;
+            }
+        }
+    }
+    (bytes / FRAME_LEN) as f64 / start.elapsed().as_secs_f64()
+}
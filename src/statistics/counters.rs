@@ -0,0 +1,124 @@
+use serde_json::{json, Value};
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals of one interface, kept across stop/listen cycles so the
+/// chart can be checked for frames that never made it into the history.
+#[derive(Default)]
+pub struct CaptureCounters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    /// Frames the kernel dropped because the capture thread fell behind.
+    kernel_drops: AtomicU64,
+    receive_errors: AtomicU64,
+    /// Frames `PackageHeader::new` could not make sense of.
+    unparseable: AtomicU64,
+    /// Flushes the ticker was not ready for, their totals arrive a tick late.
+    backpressure: AtomicU64,
+}
+
+/// What the capture thread counts between two flushes.
+#[derive(Default, PartialEq)]
+pub struct Tally {
+    pub packets: u64,
+    pub bytes: u64,
+    pub kernel_drops: u64,
+    pub receive_errors: u64,
+    pub unparseable: u64,
+    pub backpressure: u64,
+}
+
+impl CaptureCounters {
+    pub fn add(&self, tally: &Tally) {
+        for (counter, value) in [
+            (&self.packets, tally.packets),
+            (&self.bytes, tally.bytes),
+            (&self.kernel_drops, tally.kernel_drops),
+            (&self.receive_errors, tally.receive_errors),
+            (&self.unparseable, tally.unparseable),
+            (&self.backpressure, tally.backpressure),
+        ] {
+            counter.fetch_add(value, Ordering::Relaxed);
+        }
+    }
+
+    fn values(&self) -> [(&'static str, &'static str, u64); 6] {
+        [
+            (
+                "packets",
+                "Frames received by the capture thread.",
+                self.packets.load(Ordering::Relaxed),
+            ),
+            (
+                "bytes",
+                "Bytes received by the capture thread.",
+                self.bytes.load(Ordering::Relaxed),
+            ),
+            (
+                "kernel_drops",
+                "Frames dropped by the kernel before they reached the capture thread.",
+                self.kernel_drops.load(Ordering::Relaxed),
+            ),
+            (
+                "receive_errors",
+                "Errors returned by the capture backend.",
+                self.receive_errors.load(Ordering::Relaxed),
+            ),
+            (
+                "unparseable",
+                "Frames whose headers could not be parsed.",
+                self.unparseable.load(Ordering::Relaxed),
+            ),
+            (
+                "backpressure",
+                "Aggregations held back because the ticker was behind.",
+                self.backpressure.load(Ordering::Relaxed),
+            ),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut m = serde_json::Map::new();
+        for (name, _, value) in self.values() {
+            m.insert(name.to_owned(), json!(value));
+        }
+        Value::Object(m)
+    }
+
+    /// Prometheus text exposition of every interface's counters.
+    pub fn metrics<'a>(
+        interfaces: impl Iterator<Item = (&'a String, &'a CaptureCounters)>,
+    ) -> String {
+        let interfaces: Vec<_> = interfaces.map(|(name, c)| (name, c.values())).collect();
+        let mut out = String::new();
+        for (i, (name, help, _)) in CaptureCounters::default().values().iter().enumerate() {
+            let _ = writeln!(out, "# HELP network_view_capture_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE network_view_capture_{}_total counter", name);
+            for (interface, values) in interfaces.iter() {
+                let _ = writeln!(
+                    out,
+                    "network_view_capture_{}_total{{interface=\"{}\"}} {}",
+                    name,
+                    escape_label(interface),
+                    values[i].2
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Label values escape only backslashes, double quotes and line feeds.
+fn escape_label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
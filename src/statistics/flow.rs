@@ -0,0 +1,65 @@
+use std::net::SocketAddr;
+
+use super::header::IpHeader;
+
+pub const IP_PROTOCOL_TCP: u8 = 6;
+pub const IP_PROTOCOL_UDP: u8 = 17;
+const TCP_MIN_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+/// A TCP or UDP conversation, the same key for both directions.
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct FlowKey {
+    pub protocol: u8,
+    /// The lower of the two endpoints.
+    pub a: SocketAddr,
+    pub b: SocketAddr,
+}
+
+/// One TCP segment or UDP datagram.
+pub struct Segment<'a> {
+    pub key: FlowKey,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    /// The TCP header, empty for UDP.
+    pub tcp: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// `payload` is the upper layer payload `PackageHeader::parse` returns.
+    /// Fragments other than the first one carry no ports and give `None`.
+    pub fn new(ip_header: &IpHeader, payload: &'a [u8]) -> Option<Self> {
+        let (tcp, data) = match ip_header.protocol {
+            IP_PROTOCOL_TCP => {
+                let len = (*payload.get(12)? >> 4) as usize * 4;
+                if len < TCP_MIN_HEADER_LEN {
+                    return None;
+                }
+                (payload.get(..len)?, payload.get(len..)?)
+            }
+            IP_PROTOCOL_UDP => (&payload[..0], payload.get(UDP_HEADER_LEN..)?),
+            _ => return None,
+        };
+        let source_port = u16::from_be_bytes([payload[0], payload[1]]);
+        let destination_port = u16::from_be_bytes([payload[2], payload[3]]);
+        let source = SocketAddr::new(ip_header.source, source_port);
+        let destination = SocketAddr::new(ip_header.destination, destination_port);
+        let (a, b) = if source <= destination {
+            (source, destination)
+        } else {
+            (destination, source)
+        };
+        Some(Self {
+            key: FlowKey {
+                protocol: ip_header.protocol,
+                a,
+                b,
+            },
+            source,
+            destination,
+            tcp,
+            data,
+        })
+    }
+}
@@ -0,0 +1,899 @@
+use serde::{Deserialize, Serialize};
+
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::util::MacAddr;
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::capture::LinkType;
+
+const LINUX_SLL_HEADER_LEN: usize = 16;
+const LINUX_SLL2_HEADER_LEN: usize = 20;
+const NULL_HEADER_LEN: usize = 4;
+const ARPHRD_ETHER: u16 = 1;
+const VLAN_TAG_LEN: usize = 4;
+const MPLS_LABEL_LEN: usize = 4;
+/// 802.1Q, 802.1ad and the pre-standard 0x9100 QinQ tag.
+const VLAN_ETHERTYPES: [u16; 3] = [0x8100, 0x88a8, 0x9100];
+/// MPLS unicast and multicast.
+const MPLS_ETHERTYPES: [u16; 2] = [0x8847, 0x8848];
+/// Transparent Ethernet bridging, the protocol type of Ethernet in GRE and Geneve.
+const ETHERTYPE_TEB: u16 = 0x6558;
+const IP_PROTOCOL_IPIP: u8 = 4;
+const IP_PROTOCOL_UDP: u8 = 17;
+const IP_PROTOCOL_IPV6: u8 = 41;
+const IP_PROTOCOL_GRE: u8 = 47;
+const UDP_HEADER_LEN: usize = 8;
+const VXLAN_PORT: u16 = 4789;
+const VXLAN_HEADER_LEN: usize = 8;
+const GENEVE_PORT: u16 = 6081;
+const GENEVE_HEADER_LEN: usize = 8;
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_AUTHENTICATION: u8 = 51;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+const IPV6_MOBILITY: u8 = 135;
+const IPV6_HIP: u8 = 139;
+const IPV6_SHIM6: u8 = 140;
+const IPV6_FRAGMENT_HEADER_LEN: usize = 8;
+/// Tunnels inside tunnels are followed this deep.
+const MAX_TUNNEL_DEPTH: u8 = 4;
+
+/// How frames of one capture are turned into headers.
+#[derive(Clone, Copy)]
+pub struct ParseOptions {
+    pub link_type: LinkType,
+    /// Account the packets inside GRE, VXLAN, Geneve and IP-in-IP tunnels
+    /// instead of the tunnel itself.
+    pub decapsulate: bool,
+}
+
+#[derive(Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageHeader {
+    pub protocol: u16, // EtherType
+    /// Link layers without hardware addresses (tun, raw IP, loopback) leave
+    /// both MAC addresses empty.
+    pub source: Option<MacAddr>,
+    pub destination: Option<MacAddr>,
+    pub ip_header: Option<IpHeader>,
+    /// Outer VLAN ID, the service tag for QinQ.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<u16>,
+    /// Customer VLAN ID of a QinQ frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner_vlan: Option<u16>,
+    /// Top of the MPLS label stack.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mpls_label: Option<u32>,
+    /// The outermost tunnel a decapsulated packet was found in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<Tunnel>,
+    /// Server name of the TCP or UDP flow, see `CaptureConfig::inspect`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<Arc<str>>,
+}
+
+#[derive(Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tunnel {
+    pub kind: TunnelKind,
+    /// Tunnel endpoints, the outer IP addresses.
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    /// VXLAN/Geneve network identifier or GRE key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vni: Option<u32>,
+}
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelKind {
+    Gre,
+    Vxlan,
+    Geneve,
+    IpInIp,
+}
+
+#[derive(Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpHeader {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: u8, // IpNextHeaderProtocol, past any IPv6 extension headers
+    /// Part of a fragmented packet. Only the first fragment carries the
+    /// upper layer header, so fragments are kept apart from whole packets.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fragment: bool,
+}
+
+impl PackageHeader {
+    pub fn new(options: &ParseOptions, package: &[u8]) -> Option<Self> {
+        PackageHeader::parse(options, package).map(|(header, _)| header)
+    }
+
+    /// The header along with the payload behind it: the upper layer payload
+    /// of IP packets and the EtherType payload (ARP for example) otherwise.
+    pub fn parse<'a>(options: &ParseOptions, package: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let depth = if options.decapsulate {
+            MAX_TUNNEL_DEPTH
+        } else {
+            0
+        };
+        match options.link_type {
+            LinkType::Ethernet => PackageHeader::new_ethernet(package, depth),
+            LinkType::RawIp => PackageHeader::new_ip(package, depth),
+            LinkType::LinuxSll => PackageHeader::new_linux_sll(package, depth),
+            LinkType::LinuxSll2 => PackageHeader::new_linux_sll2(package, depth),
+            LinkType::Null => PackageHeader::new_ip(package.get(NULL_HEADER_LEN..)?, depth),
+        }
+    }
+
+    fn new_ethernet(package: &[u8], depth: u8) -> Option<(Self, &[u8])> {
+        let ethernet = EthernetPacket::new(package)?;
+        PackageHeader::new_ethertype(
+            ethernet.get_ethertype().0,
+            Some(ethernet.get_source()),
+            Some(ethernet.get_destination()),
+            &package[EthernetPacket::minimum_packet_size()..],
+            depth,
+        )
+    }
+
+    /// A packet without link layer header, the IP version tells its type.
+    fn new_ip(package: &[u8], depth: u8) -> Option<(Self, &[u8])> {
+        let protocol = match package.first()? >> 4 {
+            4 => EtherTypes::Ipv4,
+            6 => EtherTypes::Ipv6,
+            _ => return None,
+        };
+        PackageHeader::new_ethertype(protocol.0, None, None, package, depth)
+    }
+
+    /// Linux cooked capture v1: only the sender's address is recorded.
+    fn new_linux_sll(package: &[u8], depth: u8) -> Option<(Self, &[u8])> {
+        let header = package.get(..LINUX_SLL_HEADER_LEN)?;
+        let hatype = u16::from_be_bytes([header[2], header[3]]);
+        let halen = u16::from_be_bytes([header[4], header[5]]);
+        let source = link_address(hatype, halen as usize, &header[6..14]);
+        let protocol = u16::from_be_bytes([header[14], header[15]]);
+        PackageHeader::new_ethertype(
+            protocol,
+            source,
+            None,
+            &package[LINUX_SLL_HEADER_LEN..],
+            depth,
+        )
+    }
+
+    /// Linux cooked capture v2, as produced by libpcap for the `any` device.
+    fn new_linux_sll2(package: &[u8], depth: u8) -> Option<(Self, &[u8])> {
+        let header = package.get(..LINUX_SLL2_HEADER_LEN)?;
+        let protocol = u16::from_be_bytes([header[0], header[1]]);
+        let hatype = u16::from_be_bytes([header[8], header[9]]);
+        let source = link_address(hatype, header[11] as usize, &header[12..20]);
+        PackageHeader::new_ethertype(
+            protocol,
+            source,
+            None,
+            &package[LINUX_SLL2_HEADER_LEN..],
+            depth,
+        )
+    }
+
+    /// Strips VLAN tags and MPLS labels, so `protocol` ends up being the
+    /// EtherType of what is carried inside them.
+    fn new_ethertype(
+        mut protocol: u16,
+        source: Option<MacAddr>,
+        destination: Option<MacAddr>,
+        mut payload: &[u8],
+        depth: u8,
+    ) -> Option<(Self, &[u8])> {
+        let mut vlan = None;
+        let mut inner_vlan = None;
+        let mut mpls_label = None;
+        loop {
+            if VLAN_ETHERTYPES.contains(&protocol) {
+                let tag = payload.get(..VLAN_TAG_LEN)?;
+                let id = u16::from_be_bytes([tag[0], tag[1]]) & 0x0fff;
+                if vlan.is_none() {
+                    vlan = Some(id);
+                } else if inner_vlan.is_none() {
+                    inner_vlan = Some(id);
+                }
+                protocol = u16::from_be_bytes([tag[2], tag[3]]);
+                payload = &payload[VLAN_TAG_LEN..];
+            } else if MPLS_ETHERTYPES.contains(&protocol) {
+                let mut rest = payload;
+                loop {
+                    let entry = rest.get(..MPLS_LABEL_LEN)?;
+                    let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                    mpls_label.get_or_insert(entry >> 12);
+                    rest = &rest[MPLS_LABEL_LEN..];
+                    // bottom of stack
+                    if entry & 0x100 != 0 {
+                        break;
+                    }
+                }
+                // MPLS does not say what it carries, anything but IP (for
+                // example a pseudowire) stays accounted as MPLS
+                protocol = match rest.first().map(|b| b >> 4) {
+                    Some(4) => EtherTypes::Ipv4.0,
+                    Some(6) => EtherTypes::Ipv6.0,
+                    _ => break,
+                };
+                payload = rest;
+                break;
+            } else {
+                break;
+            }
+        }
+        let ip = match protocol {
+            p if p == EtherTypes::Ipv4.0 => Some(IpHeader::new_ipv4(payload)?),
+            p if p == EtherTypes::Ipv6.0 => Some(IpHeader::new_ipv6(payload)?),
+            _ => None,
+        };
+        if let Some((ip_header, ip_payload)) = &ip {
+            if depth > 0 {
+                if let Some(inner) = PackageHeader::decapsulate(ip_header, ip_payload, depth - 1) {
+                    return Some(inner);
+                }
+            }
+        }
+        let (ip_header, payload) = match ip {
+            Some((ip_header, ip_payload)) => (Some(ip_header), ip_payload),
+            None => (None, payload),
+        };
+        let header = PackageHeader {
+            protocol,
+            source,
+            destination,
+            ip_header,
+            vlan,
+            inner_vlan,
+            mpls_label,
+            tunnel: None,
+            host: None,
+        };
+        Some((header, payload))
+    }
+
+    /// The header of the packet carried by a tunnel, `None` when `payload`
+    /// is not a tunnel this parser knows.
+    fn decapsulate<'a>(outer: &IpHeader, payload: &'a [u8], depth: u8) -> Option<(Self, &'a [u8])> {
+        let (kind, vni, (mut inner, inner_payload)) = match outer.protocol {
+            IP_PROTOCOL_IPIP | IP_PROTOCOL_IPV6 => (
+                TunnelKind::IpInIp,
+                None,
+                PackageHeader::new_ip(payload, depth)?,
+            ),
+            IP_PROTOCOL_GRE => {
+                let (protocol, key, inner) = gre(payload)?;
+                let inner = match protocol {
+                    ETHERTYPE_TEB => PackageHeader::new_ethernet(inner, depth)?,
+                    p if p == EtherTypes::Ipv4.0 || p == EtherTypes::Ipv6.0 => {
+                        PackageHeader::new_ip(inner, depth)?
+                    }
+                    _ => return None,
+                };
+                (TunnelKind::Gre, key, inner)
+            }
+            IP_PROTOCOL_UDP => {
+                let udp = payload.get(..UDP_HEADER_LEN)?;
+                let port = u16::from_be_bytes([udp[2], udp[3]]);
+                let payload = &payload[UDP_HEADER_LEN..];
+                match port {
+                    VXLAN_PORT => {
+                        let header = payload.get(..VXLAN_HEADER_LEN)?;
+                        // the I flag marks a valid VNI
+                        if header[0] & 0x08 == 0 {
+                            return None;
+                        }
+                        let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+                        let inner = &payload[VXLAN_HEADER_LEN..];
+                        (
+                            TunnelKind::Vxlan,
+                            Some(vni),
+                            PackageHeader::new_ethernet(inner, depth)?,
+                        )
+                    }
+                    GENEVE_PORT => {
+                        let header = payload.get(..GENEVE_HEADER_LEN)?;
+                        let len = GENEVE_HEADER_LEN + (header[0] & 0x3f) as usize * 4;
+                        let protocol = u16::from_be_bytes([header[2], header[3]]);
+                        let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+                        let inner = payload.get(len..)?;
+                        let inner = match protocol {
+                            ETHERTYPE_TEB => PackageHeader::new_ethernet(inner, depth)?,
+                            _ => PackageHeader::new_ip(inner, depth)?,
+                        };
+                        (TunnelKind::Geneve, Some(vni), inner)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        inner.tunnel = Some(Tunnel {
+            kind,
+            source: outer.source,
+            destination: outer.destination,
+            vni,
+        });
+        Some((inner, inner_payload))
+    }
+}
+
+/// Splits a GRE header into protocol type, key and payload.
+fn gre(payload: &[u8]) -> Option<(u16, Option<u32>, &[u8])> {
+    let header = payload.get(..4)?;
+    let checksum = header[0] & 0x80 != 0;
+    let key = header[0] & 0x20 != 0;
+    let sequence = header[0] & 0x10 != 0;
+    // only version 0, version 1 is PPTP's enhanced GRE
+    if header[1] & 0x07 != 0 {
+        return None;
+    }
+    let protocol = u16::from_be_bytes([header[2], header[3]]);
+    let mut offset = 4;
+    if checksum {
+        offset += 4;
+    }
+    let key = if key {
+        let key = payload.get(offset..offset + 4)?;
+        offset += 4;
+        Some(u32::from_be_bytes([key[0], key[1], key[2], key[3]]))
+    } else {
+        None
+    };
+    if sequence {
+        offset += 4;
+    }
+    Some((protocol, key, payload.get(offset..)?))
+}
+
+impl IpHeader {
+    /// The header along with the packet's payload.
+    fn new_ipv4(payload: &[u8]) -> Option<(Self, &[u8])> {
+        let header = Ipv4Packet::new(payload)?;
+        let len = header.get_header_length() as usize * 4;
+        let end = (header.get_total_length() as usize).min(payload.len());
+        let more_fragments = header.get_flags() & Ipv4Flags::MoreFragments != 0;
+        let offset = header.get_fragment_offset();
+        // the upper layer header is only in the first fragment
+        let payload = match offset {
+            0 => payload.get(len..end).unwrap_or_default(),
+            _ => &[],
+        };
+        Some((
+            IpHeader {
+                source: IpAddr::V4(header.get_source()),
+                destination: IpAddr::V4(header.get_destination()),
+                protocol: header.get_next_level_protocol().0,
+                fragment: more_fragments || offset != 0,
+            },
+            payload,
+        ))
+    }
+
+    fn new_ipv6(payload: &[u8]) -> Option<(Self, &[u8])> {
+        let header = Ipv6Packet::new(payload)?;
+        let len = Ipv6Packet::minimum_packet_size();
+        let end = (len + header.get_payload_length() as usize).min(payload.len());
+        let (protocol, fragment, payload) =
+            skip_extension_headers(header.get_next_header().0, &payload[len..end]);
+        Some((
+            IpHeader {
+                source: IpAddr::V6(header.get_source()),
+                destination: IpAddr::V6(header.get_destination()),
+                protocol,
+                fragment,
+            },
+            payload,
+        ))
+    }
+}
+
+/// Walks the IPv6 extension header chain to the upper layer protocol.
+/// Returns that protocol, whether the packet is a fragment and the upper
+/// layer payload. A chain cut short by the snapshot length ends the walk at
+/// the last header that could be read.
+fn skip_extension_headers(mut next_header: u8, mut payload: &[u8]) -> (u8, bool, &[u8]) {
+    let mut fragment = false;
+    loop {
+        let len = match next_header {
+            IPV6_HOP_BY_HOP
+            | IPV6_ROUTING
+            | IPV6_DESTINATION_OPTIONS
+            | IPV6_MOBILITY
+            | IPV6_HIP
+            | IPV6_SHIM6 => match payload.get(1) {
+                Some(len) => (*len as usize + 1) * 8,
+                None => break,
+            },
+            IPV6_AUTHENTICATION => match payload.get(1) {
+                Some(len) => (*len as usize + 2) * 4,
+                None => break,
+            },
+            IPV6_FRAGMENT => {
+                let header = match payload.get(..IPV6_FRAGMENT_HEADER_LEN) {
+                    Some(header) => header,
+                    None => break,
+                };
+                fragment = true;
+                let offset = u16::from_be_bytes([header[2], header[3]]) >> 3;
+                if offset != 0 {
+                    // later fragments carry no upper layer header
+                    return (header[0], fragment, &[]);
+                }
+                IPV6_FRAGMENT_HEADER_LEN
+            }
+            _ => break,
+        };
+        match payload.get(len..) {
+            Some(rest) => {
+                next_header = payload[0];
+                payload = rest;
+            }
+            None => break,
+        }
+    }
+    (next_header, fragment, payload)
+}
+
+fn link_address(hatype: u16, halen: usize, address: &[u8]) -> Option<MacAddr> {
+    if hatype != ARPHRD_ETHER || halen != 6 {
+        return None;
+    }
+    Some(MacAddr::new(
+        address[0], address[1], address[2], address[3], address[4], address[5],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const SOURCE_MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const DESTINATION_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+    const SOURCE_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const DESTINATION_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+    const SOURCE_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    const DESTINATION_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+    const UDP_PAYLOAD: &[u8] = b"payload";
+    const TUNNEL_SOURCE: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    const TUNNEL_DESTINATION: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 2);
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = DESTINATION_MAC.to_vec();
+        frame.extend_from_slice(&SOURCE_MAC);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        ipv4_between(SOURCE_V4, DESTINATION_V4, protocol, payload)
+    }
+
+    fn ipv4_between(
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let len = (20 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+        packet.extend_from_slice(&source.octets());
+        packet.extend_from_slice(&destination.octets());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn ipv6(next_header: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[next_header, 64]);
+        packet.extend_from_slice(&SOURCE_V6.octets());
+        packet.extend_from_slice(&DESTINATION_V6.octets());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn udp(destination_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = 40000u16.to_be_bytes().to_vec();
+        datagram.extend_from_slice(&destination_port.to_be_bytes());
+        datagram.extend_from_slice(&((UDP_HEADER_LEN + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    fn options(link_type: LinkType, decapsulate: bool) -> ParseOptions {
+        ParseOptions {
+            link_type,
+            decapsulate,
+        }
+    }
+
+    /// Addresses, protocol and fragment flag of an IP header.
+    type Ip = (IpAddr, IpAddr, u8, bool);
+
+    fn ip(header: &PackageHeader) -> Option<Ip> {
+        let ip = header.ip_header.as_ref()?;
+        Some((ip.source, ip.destination, ip.protocol, ip.fragment))
+    }
+
+    fn udp_v4() -> Option<Ip> {
+        Some((
+            SOURCE_V4.into(),
+            DESTINATION_V4.into(),
+            IP_PROTOCOL_UDP,
+            false,
+        ))
+    }
+
+    /// Every prefix of `frame` is parsed, a frame cut anywhere must not
+    /// panic.
+    fn parse_truncated(options: &ParseOptions, frame: &[u8]) {
+        for len in 0..frame.len() {
+            let _ = PackageHeader::parse(options, &frame[..len]);
+        }
+    }
+
+    #[test]
+    fn ethernet_frame() {
+        let frame = ethernet(0x0800, &ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let options = options(LinkType::Ethernet, false);
+        let (header, payload) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!(header.source, Some(MacAddr::from(SOURCE_MAC)));
+        assert_eq!(header.destination, Some(MacAddr::from(DESTINATION_MAC)));
+        assert_eq!(ip(&header), udp_v4());
+        assert_eq!(payload, udp(53, UDP_PAYLOAD));
+        // shorter than the Ethernet header
+        assert!(PackageHeader::parse(&options, &frame[..13]).is_none());
+        parse_truncated(&options, &frame);
+    }
+
+    #[test]
+    fn raw_ip_packets() {
+        let options = options(LinkType::RawIp, false);
+        let packet = ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD));
+        let (header, payload) = PackageHeader::parse(&options, &packet).unwrap();
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!((header.source, header.destination), (None, None));
+        assert_eq!(ip(&header), udp_v4());
+        assert_eq!(payload, udp(53, UDP_PAYLOAD));
+        parse_truncated(&options, &packet);
+
+        let packet = ipv6(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD));
+        let (header, _) = PackageHeader::parse(&options, &packet).unwrap();
+        assert_eq!(header.protocol, 0x86dd);
+        assert_eq!(
+            ip(&header),
+            Some((
+                SOURCE_V6.into(),
+                DESTINATION_V6.into(),
+                IP_PROTOCOL_UDP,
+                false
+            ))
+        );
+        parse_truncated(&options, &packet);
+
+        // neither version 4 nor 6
+        assert!(PackageHeader::parse(&options, &[0x50; 40]).is_none());
+    }
+
+    #[test]
+    fn linux_cooked_v1() {
+        let mut frame = vec![0, 4];
+        frame.extend_from_slice(&ARPHRD_ETHER.to_be_bytes());
+        frame.extend_from_slice(&6u16.to_be_bytes());
+        frame.extend_from_slice(&SOURCE_MAC);
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let options = options(LinkType::LinuxSll, false);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!(header.source, Some(MacAddr::from(SOURCE_MAC)));
+        assert_eq!(header.destination, None);
+        assert_eq!(ip(&header), udp_v4());
+        assert!(PackageHeader::parse(&options, &frame[..LINUX_SLL_HEADER_LEN - 1]).is_none());
+        parse_truncated(&options, &frame);
+    }
+
+    #[test]
+    fn linux_cooked_v2() {
+        let mut frame = 0x86ddu16.to_be_bytes().to_vec();
+        frame.extend_from_slice(&[0, 0, 0, 0, 0, 1]); // reserved, ifindex
+        frame.extend_from_slice(&ARPHRD_ETHER.to_be_bytes());
+        frame.extend_from_slice(&[4, 6]); // packet type, address length
+        frame.extend_from_slice(&SOURCE_MAC);
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&ipv6(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let options = options(LinkType::LinuxSll2, false);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x86dd);
+        assert_eq!(header.source, Some(MacAddr::from(SOURCE_MAC)));
+        assert_eq!(
+            ip(&header),
+            Some((
+                SOURCE_V6.into(),
+                DESTINATION_V6.into(),
+                IP_PROTOCOL_UDP,
+                false
+            ))
+        );
+        assert!(PackageHeader::parse(&options, &frame[..LINUX_SLL2_HEADER_LEN - 1]).is_none());
+        parse_truncated(&options, &frame);
+    }
+
+    #[test]
+    fn bsd_loopback() {
+        // AF_INET in host byte order
+        let mut frame = 2u32.to_ne_bytes().to_vec();
+        frame.extend_from_slice(&ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let options = options(LinkType::Null, false);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!((header.source, header.destination), (None, None));
+        assert_eq!(ip(&header), udp_v4());
+        assert!(PackageHeader::parse(&options, &frame[..NULL_HEADER_LEN]).is_none());
+        parse_truncated(&options, &frame);
+    }
+
+    /// A VLAN tag with `id` followed by `ethertype`.
+    fn vlan_tag(id: u16, ethertype: u16) -> [u8; VLAN_TAG_LEN] {
+        let [a, b] = (0x2000 | id).to_be_bytes(); // priority 1
+        let [c, d] = ethertype.to_be_bytes();
+        [a, b, c, d]
+    }
+
+    fn mpls_entry(label: u32, bottom: bool) -> [u8; MPLS_LABEL_LEN] {
+        (label << 12 | (bottom as u32) << 8 | 64).to_be_bytes()
+    }
+
+    #[test]
+    fn vlan_tags() {
+        let options = options(LinkType::Ethernet, false);
+        let mut tagged = vlan_tag(100, 0x0800).to_vec();
+        tagged.extend_from_slice(&ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let frame = ethernet(0x8100, &tagged);
+        let (header, payload) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!((header.vlan, header.inner_vlan), (Some(100), None));
+        assert_eq!(ip(&header), udp_v4());
+        assert_eq!(payload, udp(53, UDP_PAYLOAD));
+        parse_truncated(&options, &frame);
+        // cut inside the tag
+        assert!(PackageHeader::parse(&options, &frame[..16]).is_none());
+
+        let mut qinq = vlan_tag(200, 0x8100).to_vec();
+        qinq.extend_from_slice(&vlan_tag(300, 0x86dd));
+        qinq.extend_from_slice(&ipv6(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let frame = ethernet(0x88a8, &qinq);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x86dd);
+        assert_eq!((header.vlan, header.inner_vlan), (Some(200), Some(300)));
+        parse_truncated(&options, &frame);
+        assert!(PackageHeader::parse(&options, &frame[..20]).is_none());
+    }
+
+    #[test]
+    fn mpls_label_stack() {
+        let options = options(LinkType::Ethernet, false);
+        let mut stack = mpls_entry(1000, false).to_vec();
+        stack.extend_from_slice(&mpls_entry(2000, true));
+        let mut labelled = stack.clone();
+        labelled.extend_from_slice(&ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)));
+        let frame = ethernet(0x8847, &labelled);
+        let (header, payload) = PackageHeader::parse(&options, &frame).unwrap();
+        // the top label, and IP guessed from the version nibble
+        assert_eq!(header.mpls_label, Some(1000));
+        assert_eq!(header.protocol, 0x0800);
+        assert_eq!(ip(&header), udp_v4());
+        assert_eq!(payload, udp(53, UDP_PAYLOAD));
+        parse_truncated(&options, &frame);
+        // cut inside the second label
+        assert!(PackageHeader::parse(&options, &frame[..20]).is_none());
+
+        // a pseudowire stays accounted as MPLS
+        let mut pseudowire = stack;
+        pseudowire.extend_from_slice(&[0; 4]);
+        let frame = ethernet(0x8847, &pseudowire);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert_eq!(header.protocol, 0x8847);
+        assert_eq!(header.mpls_label, Some(1000));
+        assert!(header.ip_header.is_none());
+        parse_truncated(&options, &frame);
+    }
+
+    /// An Ethernet frame carrying `payload` between the tunnel endpoints.
+    fn tunnel(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        ethernet(
+            0x0800,
+            &ipv4_between(TUNNEL_SOURCE, TUNNEL_DESTINATION, protocol, payload),
+        )
+    }
+
+    /// Parses `frame` decapsulated, checks the tunnel and the inner UDP
+    /// packet, and that a frame cut inside the tunnel header at `cut` is
+    /// accounted as the tunnel packet.
+    fn assert_tunnel(frame: &[u8], kind: TunnelKind, vni: Option<u32>, cut: usize) {
+        let options = options(LinkType::Ethernet, true);
+        let (header, payload) = PackageHeader::parse(&options, frame).unwrap();
+        let tunnel = header.tunnel.as_ref().unwrap();
+        assert!(tunnel.kind == kind);
+        assert_eq!(tunnel.source, IpAddr::from(TUNNEL_SOURCE));
+        assert_eq!(tunnel.destination, IpAddr::from(TUNNEL_DESTINATION));
+        assert_eq!(tunnel.vni, vni);
+        assert_eq!(ip(&header), udp_v4());
+        assert_eq!(payload, udp(53, UDP_PAYLOAD));
+        parse_truncated(&options, frame);
+
+        let (header, _) = PackageHeader::parse(&options, &frame[..cut]).unwrap();
+        assert!(header.tunnel.is_none());
+        assert_eq!(ip(&header).unwrap().0, IpAddr::from(TUNNEL_SOURCE));
+    }
+
+    #[test]
+    fn gre() {
+        let inner = ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD));
+        // key present, IPv4 inside
+        let mut gre = vec![0x20, 0];
+        gre.extend_from_slice(&0x0800u16.to_be_bytes());
+        gre.extend_from_slice(&42u32.to_be_bytes());
+        gre.extend_from_slice(&inner);
+        let frame = tunnel(IP_PROTOCOL_GRE, &gre);
+        assert_tunnel(&frame, TunnelKind::Gre, Some(42), 14 + 20 + 6);
+
+        // without decapsulation the tunnel itself is accounted
+        let options = options(LinkType::Ethernet, false);
+        let (header, _) = PackageHeader::parse(&options, &frame).unwrap();
+        assert!(header.tunnel.is_none());
+        assert_eq!(ip(&header).unwrap().2, IP_PROTOCOL_GRE);
+
+        // Ethernet inside
+        let mut gre = vec![0, 0];
+        gre.extend_from_slice(&ETHERTYPE_TEB.to_be_bytes());
+        gre.extend_from_slice(&ethernet(0x0800, &inner));
+        assert_tunnel(
+            &tunnel(IP_PROTOCOL_GRE, &gre),
+            TunnelKind::Gre,
+            None,
+            14 + 20 + 2,
+        );
+    }
+
+    #[test]
+    fn vxlan() {
+        let mut vxlan = vec![0x08, 0, 0, 0, 0, 0x12, 0x34, 0];
+        vxlan.extend_from_slice(&ethernet(
+            0x0800,
+            &ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)),
+        ));
+        let frame = tunnel(IP_PROTOCOL_UDP, &udp(VXLAN_PORT, &vxlan));
+        assert_tunnel(&frame, TunnelKind::Vxlan, Some(0x1234), 14 + 20 + 8 + 6);
+    }
+
+    #[test]
+    fn geneve() {
+        // one option word
+        let mut geneve = vec![0x01, 0];
+        geneve.extend_from_slice(&ETHERTYPE_TEB.to_be_bytes());
+        geneve.extend_from_slice(&[0, 0x56, 0x78, 0]);
+        geneve.extend_from_slice(&[0xff; 4]);
+        geneve.extend_from_slice(&ethernet(
+            0x0800,
+            &ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)),
+        ));
+        let frame = tunnel(IP_PROTOCOL_UDP, &udp(GENEVE_PORT, &geneve));
+        // cut inside the option
+        assert_tunnel(&frame, TunnelKind::Geneve, Some(0x5678), 14 + 20 + 8 + 10);
+    }
+
+    #[test]
+    fn ip_in_ip() {
+        let frame = tunnel(
+            IP_PROTOCOL_IPIP,
+            &ipv4(IP_PROTOCOL_UDP, &udp(53, UDP_PAYLOAD)),
+        );
+        assert_tunnel(&frame, TunnelKind::IpInIp, None, 14 + 20 + 10);
+    }
+
+    /// An IPv6 extension header of `len` bytes followed by `payload`, the
+    /// length field in the units of `kind`.
+    fn extension(kind: u8, next_header: u8, len: usize, payload: &[u8]) -> Vec<u8> {
+        let units = match kind {
+            IPV6_AUTHENTICATION => len / 4 - 2,
+            _ => len / 8 - 1,
+        };
+        let mut header = vec![0; len];
+        header[0] = next_header;
+        header[1] = units as u8;
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn fragment(next_header: u8, offset: u16, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut header = vec![next_header, 0];
+        header.extend_from_slice(&(offset << 3 | more as u16).to_be_bytes());
+        header.extend_from_slice(&[0, 0, 0, 1]);
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn parse_ipv6(packet: &[u8]) -> (Option<Ip>, Vec<u8>) {
+        let (header, payload) =
+            PackageHeader::parse(&options(LinkType::RawIp, false), packet).unwrap();
+        (ip(&header), payload.to_vec())
+    }
+
+    fn udp_v6(fragment: bool) -> Option<Ip> {
+        Some((
+            SOURCE_V6.into(),
+            DESTINATION_V6.into(),
+            IP_PROTOCOL_UDP,
+            fragment,
+        ))
+    }
+
+    #[test]
+    fn ipv6_extension_headers() {
+        let datagram = udp(53, UDP_PAYLOAD);
+        let destination = extension(IPV6_DESTINATION_OPTIONS, IP_PROTOCOL_UDP, 16, &datagram);
+        let packet = ipv6(
+            IPV6_HOP_BY_HOP,
+            &extension(IPV6_HOP_BY_HOP, IPV6_DESTINATION_OPTIONS, 8, &destination),
+        );
+        assert_eq!(parse_ipv6(&packet), (udp_v6(false), datagram.clone()));
+        parse_truncated(&options(LinkType::RawIp, false), &packet);
+        // cut inside the destination options, the walk ends before them
+        let (header, payload) = parse_ipv6(&packet[..40 + 8 + 4]);
+        assert_eq!(header.unwrap().2, IPV6_DESTINATION_OPTIONS);
+        assert_eq!(payload.len(), 4);
+
+        let packet = ipv6(
+            IPV6_AUTHENTICATION,
+            &extension(IPV6_AUTHENTICATION, IP_PROTOCOL_UDP, 12, &datagram),
+        );
+        assert_eq!(parse_ipv6(&packet), (udp_v6(false), datagram));
+    }
+
+    #[test]
+    fn fragments() {
+        let datagram = udp(53, UDP_PAYLOAD);
+        // the first fragment carries the UDP header
+        let packet = ipv6(
+            IPV6_FRAGMENT,
+            &fragment(IP_PROTOCOL_UDP, 0, true, &datagram),
+        );
+        assert_eq!(parse_ipv6(&packet), (udp_v6(true), datagram.clone()));
+        parse_truncated(&options(LinkType::RawIp, false), &packet);
+        // later ones carry none
+        let packet = ipv6(
+            IPV6_FRAGMENT,
+            &fragment(IP_PROTOCOL_UDP, 185, false, &[0; 16]),
+        );
+        assert_eq!(parse_ipv6(&packet), (udp_v6(true), Vec::new()));
+
+        let options = options(LinkType::RawIp, false);
+        let mut packet = ipv4(IP_PROTOCOL_UDP, &datagram);
+        packet[6] = 0x20; // more fragments
+        let (header, payload) = PackageHeader::parse(&options, &packet).unwrap();
+        assert!(ip(&header).unwrap().3);
+        assert_eq!(payload, datagram);
+        packet[6..8].copy_from_slice(&185u16.to_be_bytes());
+        let (header, payload) = PackageHeader::parse(&options, &packet).unwrap();
+        assert!(ip(&header).unwrap().3);
+        assert!(payload.is_empty());
+    }
+}
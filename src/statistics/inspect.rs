@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::flow::{FlowKey, Segment, IP_PROTOCOL_TCP};
+use super::quic;
+
+const TLS_HANDSHAKE: u8 = 22;
+const TLS_RECORD_HEADER_LEN: usize = 5;
+const CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const HOST_NAME: u8 = 0;
+const HTTPS_PORT: u16 = 443;
+/// Request line and headers searched for `Host`.
+const MAX_HTTP_HEADER_LEN: usize = 4096;
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"HEAD ",
+    b"PUT ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+/// Forget idle flows beyond this many.
+const MAX_FLOWS: usize = 65536;
+/// Flushes a flow may stay idle before it is forgotten, about five minutes.
+const IDLE_EPOCHS: u64 = 3000;
+
+/// Hostnames announced by the clients of TCP and UDP flows: the TLS and
+/// QUIC server name indication or the HTTP `Host` header. Only the first
+/// segment carrying data is looked at, later traffic of the flow is
+/// attributed to the name found there, or to none.
+#[derive(Default)]
+pub struct Hostnames {
+    /// Flows whose first data segment was seen, with the name it carried.
+    flows: HashMap<FlowKey, (Option<Arc<str>>, u64)>,
+    epoch: u64,
+    swept: u64,
+}
+
+impl Hostnames {
+    pub fn lookup(&mut self, segment: &Segment) -> Option<Arc<str>> {
+        if let Some((host, epoch)) = self.flows.get_mut(&segment.key) {
+            *epoch = self.epoch;
+            return host.clone();
+        }
+        if segment.data.is_empty() {
+            return None;
+        }
+        let host = if segment.key.protocol == IP_PROTOCOL_TCP {
+            tls_server_name(segment.data).or_else(|| http_host(segment.data))
+        } else if segment.destination.port() == HTTPS_PORT {
+            quic::server_name(segment.data)
+        } else {
+            None
+        };
+        let host: Option<Arc<str>> = host.map(Into::into);
+        self.flows.insert(segment.key, (host.clone(), self.epoch));
+        host
+    }
+
+    /// Called on every flush.
+    pub fn tick(&mut self) {
+        self.epoch += 1;
+        if self.flows.len() >= MAX_FLOWS || self.epoch - self.swept >= IDLE_EPOCHS {
+            let now = self.epoch;
+            self.swept = now;
+            self.flows.retain(|_, (_, seen)| now - *seen < IDLE_EPOCHS);
+        }
+        if self.flows.len() >= MAX_FLOWS {
+            self.flows.clear();
+        }
+    }
+}
+
+/// The server name of a TLS ClientHello starting a TCP stream.
+fn tls_server_name(data: &[u8]) -> Option<String> {
+    if *data.first()? != TLS_HANDSHAKE {
+        return None;
+    }
+    client_hello_server_name(data.get(TLS_RECORD_HEADER_LEN..)?)
+}
+
+/// The server name in a ClientHello handshake message, which may be cut
+/// short when it did not fit into the first segment.
+pub fn client_hello_server_name(handshake: &[u8]) -> Option<String> {
+    if *handshake.first()? != CLIENT_HELLO {
+        return None;
+    }
+    // type, length, version, random
+    let mut reader = Reader(handshake.get(4 + 2 + 32..)?);
+    reader.vector(1)?; // session id
+    reader.vector(2)?; // cipher suites
+    reader.vector(1)?; // compression methods
+    let mut extensions = Reader(reader.vector_truncated(2)?);
+    while let (Some(kind), Some(data)) = (extensions.u16(), extensions.vector(2)) {
+        if kind != EXTENSION_SERVER_NAME {
+            continue;
+        }
+        let mut names = Reader(Reader(data).vector(2)?);
+        while let Some(name_type) = names.u8() {
+            let name = names.vector(2)?;
+            if name_type == HOST_NAME {
+                return hostname(name);
+            }
+        }
+        return None;
+    }
+    None
+}
+
+/// The `Host` header of a plaintext HTTP/1 request.
+fn http_host(data: &[u8]) -> Option<String> {
+    if !HTTP_METHODS.iter().any(|method| data.starts_with(method)) {
+        return None;
+    }
+    let head = &data[..data.len().min(MAX_HTTP_HEADER_LEN)];
+    for line in head.split(|&c| c == b'\n').skip(1) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.len() > 5 && line[..5].eq_ignore_ascii_case(b"host:") {
+            let value = std::str::from_utf8(&line[5..]).ok()?.trim();
+            // drop the port, but not the colons of a bracketed IPv6 literal
+            let host = match value.rfind(':') {
+                Some(i) if !value[i..].contains(']') => &value[..i],
+                _ => value,
+            };
+            return hostname(host.as_bytes());
+        }
+    }
+    None
+}
+
+fn hostname(name: &[u8]) -> Option<String> {
+    let name = std::str::from_utf8(name).ok()?;
+    if name.is_empty() || name.len() > 253 || name.chars().any(|c| c.is_control() || c == ' ') {
+        return None;
+    }
+    Some(name.to_ascii_lowercase())
+}
+
+/// Big endian fields and length prefixed vectors of TLS messages.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (head, rest) = (self.0.get(..len)?, &self.0[len..]);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn length(&mut self, size: usize) -> Option<usize> {
+        let bytes = self.take(size)?;
+        Some(bytes.iter().fold(0, |n, &b| n << 8 | b as usize))
+    }
+
+    fn vector(&mut self, size: usize) -> Option<&'a [u8]> {
+        let len = self.length(size)?;
+        self.take(len)
+    }
+
+    /// A vector which may extend past the end of the data at hand.
+    fn vector_truncated(&mut self, size: usize) -> Option<&'a [u8]> {
+        let len = self.length(size)?;
+        self.take(len.min(self.0.len()))
+    }
+}
@@ -0,0 +1,226 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::Shared;
+use futures::FutureExt;
+use pnet::util::MacAddr;
+use serde::Serialize;
+use tokio::time::{Duration, MissedTickBehavior};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use super::counters::{CaptureCounters, Tally};
+use super::header::PackageHeader;
+use super::Aggregation;
+
+/// How often the `counters` backend reads the kernel counters.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Totals the kernel keeps for every interface, readable without any
+/// privileges.
+#[derive(Clone, Copy, Default, Serialize)]
+pub struct KernelCounters {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+impl KernelCounters {
+    /// From sysfs, or from `/proc/net/dev` where sysfs is not mounted.
+    pub fn read(name: &str) -> io::Result<Self> {
+        Self::read_sysfs(name).or_else(|_| Self::read_proc(name))
+    }
+
+    fn read_sysfs(name: &str) -> io::Result<Self> {
+        let read = |counter: &str| -> io::Result<u64> {
+            let path = format!("/sys/class/net/{}/statistics/{}", name, counter);
+            fs::read_to_string(path)?
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed counter"))
+        };
+        Ok(Self {
+            rx_bytes: read("rx_bytes")?,
+            rx_packets: read("rx_packets")?,
+            rx_errors: read("rx_errors")?,
+            rx_dropped: read("rx_dropped")?,
+            tx_bytes: read("tx_bytes")?,
+            tx_packets: read("tx_packets")?,
+            tx_errors: read("tx_errors")?,
+            tx_dropped: read("tx_dropped")?,
+        })
+    }
+
+    fn read_proc(name: &str) -> io::Result<Self> {
+        let dev = fs::read_to_string("/proc/net/dev")?;
+        // two header lines, then `name: ` followed by eight receive and
+        // eight transmit columns, bytes packets errs drop come first in each
+        for line in dev.lines().skip(2) {
+            let (interface, values) = match line.split_once(':') {
+                Some(split) => split,
+                None => continue,
+            };
+            if interface.trim() != name {
+                continue;
+            }
+            let values: Vec<u64> = values
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            if values.len() < 12 {
+                break;
+            }
+            return Ok(Self {
+                rx_bytes: values[0],
+                rx_packets: values[1],
+                rx_errors: values[2],
+                rx_dropped: values[3],
+                tx_bytes: values[8],
+                tx_packets: values[9],
+                tx_errors: values[10],
+                tx_dropped: values[11],
+            });
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "interface not in /proc/net/dev",
+        ))
+    }
+
+    /// The increase since `earlier`. Counters which went backwards were
+    /// reset, by a driver reload for example, and count from zero.
+    pub fn since(&self, earlier: &Self) -> Self {
+        let delta = |now: u64, then: u64| now.checked_sub(then).unwrap_or(now);
+        Self {
+            rx_bytes: delta(self.rx_bytes, earlier.rx_bytes),
+            rx_packets: delta(self.rx_packets, earlier.rx_packets),
+            rx_errors: delta(self.rx_errors, earlier.rx_errors),
+            rx_dropped: delta(self.rx_dropped, earlier.rx_dropped),
+            tx_bytes: delta(self.tx_bytes, earlier.tx_bytes),
+            tx_packets: delta(self.tx_packets, earlier.tx_packets),
+            tx_errors: delta(self.tx_errors, earlier.tx_errors),
+            tx_dropped: delta(self.tx_dropped, earlier.tx_dropped),
+        }
+    }
+}
+
+/// The latest totals and the increase of the second before, sampled every
+/// tick for interfaces which are listened to.
+#[derive(Default)]
+pub struct KernelSample {
+    totals: Option<KernelCounters>,
+    pub rate: Option<KernelCounters>,
+}
+
+impl KernelSample {
+    pub fn update(&mut self, totals: Option<KernelCounters>) {
+        self.rate = match (&totals, &self.totals) {
+            (Some(now), Some(then)) => Some(now.since(then)),
+            _ => None,
+        };
+        self.totals = totals;
+    }
+}
+
+/// The totals of every interface in `names`, read on a blocking thread
+/// since sysfs reads can stall.
+pub async fn read_all(names: Vec<String>) -> HashMap<String, Option<KernelCounters>> {
+    tokio::task::spawn_blocking(move || {
+        names
+            .into_iter()
+            .map(|name| {
+                let totals = KernelCounters::read(&name).ok();
+                (name, totals)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+async fn read(name: &str) -> io::Result<KernelCounters> {
+    let name = name.to_owned();
+    tokio::task::spawn_blocking(move || KernelCounters::read(&name))
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// A header without anything but the direction, for traffic only known by
+/// its total.
+fn header(source: Option<MacAddr>, destination: Option<MacAddr>) -> PackageHeader {
+    PackageHeader {
+        protocol: 0,
+        source,
+        destination,
+        ip_header: None,
+        vlan: None,
+        inner_vlan: None,
+        mpls_label: None,
+        tunnel: None,
+        host: None,
+    }
+}
+
+/// The `counters` backend: fills the history from the kernel counters
+/// rather than from a capture. Received bytes are accounted as sent to the
+/// interface's MAC and transmitted bytes as sent from it, so the chart
+/// still tells the directions apart. Returns once `closed` fires or the
+/// receiving side has gone away.
+pub async fn poll(
+    name: &str,
+    mac: Option<MacAddr>,
+    mut tx: mpsc::Sender<Aggregation>,
+    counters: &CaptureCounters,
+    closed: Shared<oneshot::Receiver<()>>,
+) -> io::Result<()> {
+    let mut previous = read(name).await?;
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval.tick().await;
+    let mut closed = closed.fuse();
+    let mut aggregation = Aggregation::default();
+    loop {
+        futures::select! {
+            _ = interval.tick().fuse() => {}
+            _ = closed => return Ok(()),
+        }
+        let current = read(name).await?;
+        let delta = current.since(&previous);
+        previous = current;
+        counters.add(&Tally {
+            packets: delta.rx_packets + delta.tx_packets,
+            bytes: delta.rx_bytes + delta.tx_bytes,
+            kernel_drops: delta.rx_dropped,
+            receive_errors: delta.rx_errors,
+            ..Default::default()
+        });
+        // without a MAC both directions end up in the same header
+        let headers = &mut aggregation.headers;
+        if delta.rx_bytes > 0 {
+            *headers.entry(header(None, mac)).or_insert(0) += delta.rx_bytes as usize;
+        }
+        if delta.tx_bytes > 0 {
+            *headers.entry(header(mac, None)).or_insert(0) += delta.tx_bytes as usize;
+        }
+        if aggregation.is_empty() {
+            continue;
+        }
+        match tx.try_send(std::mem::take(&mut aggregation)) {
+            Ok(()) => {}
+            // the ticker is behind, the totals go along with the next ones
+            Err(e) if e.is_full() => {
+                counters.add(&Tally {
+                    backpressure: 1,
+                    ..Default::default()
+                });
+                aggregation = e.into_inner();
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,932 @@
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex;
+use futures::{FutureExt, SinkExt};
+use serde_json::{json, Value};
+use tokio::time::{Duration, MissedTickBehavior};
+use tracing::{info, warn, Instrument};
+
+use pnet::datalink::{self, NetworkInterface};
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::alert::Alerts;
+use crate::capture::{self, Backend, CaptureConfig, CaptureSource};
+use crate::collector::RemoteSource;
+use crate::dns::reverse::ReverseDns;
+use crate::export::{Exporter, FlowCache};
+use crate::privilege;
+use crate::vendor::Vendors;
+
+mod anomaly;
+pub mod benchmark;
+mod counters;
+mod flow;
+mod header;
+mod inspect;
+mod kernel;
+mod names;
+mod neighbor;
+mod quic;
+mod remote;
+mod tcp;
+
+use anomaly::{AnomalyDetector, Attempts};
+pub use counters::CaptureCounters;
+use counters::Tally;
+use flow::Segment;
+use header::{PackageHeader, ParseOptions};
+use inspect::Hostnames;
+pub use kernel::KernelCounters;
+use kernel::KernelSample;
+use names::{DnsAnswers, NameCache};
+use neighbor::{NeighborTable, Sightings};
+pub use remote::RemoteInterfaces;
+use tcp::{FlowTable, FlowUpdates, TcpTracker};
+
+/// How long the capture thread aggregates before handing its totals over.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+/// Aggregations queued for the ticker, a little more than one tick's worth.
+const CHANNEL_CAPACITY: usize = 16;
+/// Give up on a backend which keeps failing instead of spinning on it.
+const MAX_CONSECUTIVE_ERRORS: u32 = 100;
+
+/// What the capture thread hands over every `FLUSH_INTERVAL`.
+#[derive(Default)]
+struct Aggregation {
+    headers: HashMap<PackageHeader, usize>,
+    neighbors: Sightings,
+    dns: DnsAnswers,
+    flows: Vec<FlowUpdates>,
+    attempts: Attempts,
+}
+
+impl Aggregation {
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+            && self.neighbors.is_empty()
+            && self.dns.is_empty()
+            && self.flows.is_empty()
+            && self.attempts.is_empty()
+    }
+
+    fn merge(&mut self, other: Aggregation) {
+        for (header, len) in other.headers {
+            *self.headers.entry(header).or_insert(0) += len;
+        }
+        for (mac, ips) in other.neighbors {
+            self.neighbors.entry(mac).or_default().extend(ips);
+        }
+        self.dns.extend(other.dns);
+        self.flows.extend(other.flows);
+        for (source, ports) in other.attempts {
+            self.attempts.entry(source).or_default().extend(ports);
+        }
+    }
+}
+
+pub async fn statistics(
+    start_time: std::time::Instant,
+    map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>,
+    alerts: Option<Arc<Alerts>>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        // read without holding the map, requests wait for it
+        let names = {
+            let map = map.lock().await;
+            map.iter()
+                .filter(|(_, value)| value.samples_kernel())
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+        let mut kernel = kernel::read_all(names).await;
+        let mut map = map.lock().await;
+        let elapsed = start_time.elapsed().as_millis();
+        for (name, value) in map.iter_mut() {
+            if let Some(totals) = kernel.remove(name) {
+                value.kernel.update(totals);
+            }
+            value.update(elapsed, 60);
+        }
+        if let Some(alerts) = &alerts {
+            alerts.evaluate(elapsed as u64, &map);
+        }
+    }
+}
+
+pub async fn start_statistics_interface(
+    interface_name: String,
+    config: CaptureConfig,
+    default_backend: Backend,
+    exporter: Option<Exporter>,
+    start_time: std::time::Instant,
+    map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>,
+) {
+    let interface_names_match = |iface: &NetworkInterface| iface.name == interface_name;
+
+    // Find the network interface with the provided name
+    let interfaces = datalink::interfaces();
+    let interface = interfaces.into_iter().find(interface_names_match);
+    let mac = match &interface {
+        Some(interface) => interface.mac,
+        None => None,
+    };
+
+    let (tx, closed, counters) = {
+        let mut map = map.lock().await;
+        let elapsed = start_time.elapsed().as_millis();
+        match map.get_mut(&interface_name) {
+            Some(s) => {
+                if s.closed.1.is_some() {
+                    return;
+                }
+                let (tx, rx) = oneshot::channel();
+                let rx = rx.shared();
+                let (buffer_tx, buffer_rx) = mpsc::channel(CHANNEL_CAPACITY);
+                s.history.push_back((elapsed as u64, Default::default()));
+                s.closed = (rx.clone(), Some(tx));
+                s.resume = config.persistent.then(|| config.clone());
+                if config.anomalies && s.anomalies.is_none() {
+                    s.anomalies = Some(AnomalyDetector::new(elapsed as u64));
+                }
+                // the collector attaches again with the next traffic
+                if s.remote.is_some() {
+                    return;
+                }
+                s.buffer = Some(buffer_rx);
+                (buffer_tx, rx, s.counters.clone())
+            }
+            None => {
+                let (tx, rx) = oneshot::channel();
+                let rx = rx.shared();
+                let (buffer_tx, buffer_rx) = mpsc::channel(CHANNEL_CAPACITY);
+                let statistics = InterfaceStatistics {
+                    buffer: Some(buffer_rx),
+                    history: VecDeque::from([(elapsed as u64, Default::default())]),
+                    closed: (rx.clone(), Some(tx)),
+                    mac,
+                    counters: Default::default(),
+                    neighbors: Default::default(),
+                    names: Default::default(),
+                    flows: Default::default(),
+                    anomalies: config
+                        .anomalies
+                        .then(|| AnomalyDetector::new(elapsed as u64)),
+                    kernel: Default::default(),
+                    resume: config.persistent.then(|| config.clone()),
+                    remote: None,
+                };
+                let counters = statistics.counters.clone();
+                map.insert(interface_name.clone(), statistics);
+                (buffer_tx, rx, counters)
+            }
+        }
+    };
+
+    // a capture outlives the connection which asked for it
+    let span = tracing::info_span!(
+        parent: None,
+        "capture",
+        interface = %interface_name,
+        backend = ?config.backend.unwrap_or(default_backend),
+    );
+    statistics_interface(
+        &interface_name,
+        interface.as_ref(),
+        &config,
+        default_backend,
+        start_time,
+        exporter,
+        tx,
+        counters,
+        closed.clone(),
+    )
+    .instrument(span)
+    .await;
+
+    {
+        let mut map = map.lock().await;
+        match map.get_mut(&interface_name) {
+            // unless it was stopped, or cleared, and listened to again
+            // meanwhile, the entry then belongs to the newer capture
+            Some(s) if s.closed.0.ptr_eq(&closed) => s.close(),
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn statistics_interface(
+    name: &str,
+    interface: Option<&NetworkInterface>,
+    config: &CaptureConfig,
+    default_backend: Backend,
+    start_time: Instant,
+    exporter: Option<Exporter>,
+    tx: mpsc::Sender<Aggregation>,
+    counters: Arc<CaptureCounters>,
+    mut closed: futures::future::Shared<oneshot::Receiver<()>>,
+) {
+    info!("start listen");
+
+    if config.backend.unwrap_or(default_backend) == Backend::Counters {
+        let mac = interface.and_then(|interface| interface.mac);
+        if let Err(e) = kernel::poll(name, mac, tx, &counters, closed).await {
+            warn!(error = ?e, "kernel counters error");
+        }
+        info!("exit listen");
+        return;
+    }
+
+    let rx = match capture::open(interface, config, default_backend) {
+        Ok(rx) => rx,
+        Err(e) => {
+            warn!(error = ?e, "exit listen since error unable to open capture");
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                warn!("needs capture privileges: {}", privilege::HINT);
+            }
+            return;
+        }
+    };
+    let config = config.clone();
+    let networks = match interface {
+        Some(interface) => interface.ips.clone(),
+        None => Vec::new(),
+    };
+    let export = exporter.map(|exporter| FlowCache::new(exporter, interface));
+    let span = tracing::Span::current();
+    let mut capture = tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        capture_loop(rx, tx, &counters, &config, &networks, start_time, export)
+    })
+    .fuse();
+
+    let res = futures::select! {
+        res = capture => res,
+        // the thread notices within a read timeout, its flows are still
+        // to be exported
+        _ = closed => capture.await,
+    };
+    if let Ok(Err(e)) = res {
+        warn!(error = ?e, "receive error");
+    }
+
+    info!("exit listen");
+}
+
+/// Runs on the blocking capture thread, which owns the aggregation: frames
+/// are counted without any locking and the totals are handed to the ticker
+/// through the channel every `FLUSH_INTERVAL`, along with the counters.
+/// `networks` are the interface's own, neighbors are learned within them.
+/// Returns within a read timeout once the receiving side has gone away,
+/// closing the capture source.
+fn capture_loop(
+    mut rx: Box<dyn CaptureSource>,
+    mut tx: mpsc::Sender<Aggregation>,
+    counters: &CaptureCounters,
+    config: &CaptureConfig,
+    networks: &[IpNetwork],
+    start_time: Instant,
+    mut export: Option<FlowCache>,
+) -> std::io::Result<()> {
+    let mut aggregation = Aggregation::default();
+    let mut tally = Tally::default();
+    let mut dropped = 0;
+    let mut consecutive_errors = 0;
+    let mut flushed = Instant::now();
+    let options = ParseOptions {
+        link_type: rx.link_type(),
+        decapsulate: config.decapsulate,
+    };
+    let mut hostnames = config.inspect.then(Hostnames::default);
+    let mut tcp = config.track_flows.then(|| TcpTracker::new(start_time));
+    loop {
+        // every backend returns within `READ_TIMEOUT`, so a stopped capture
+        // ends even on an idle interface
+        if tx.is_closed() {
+            counters.add(&tally);
+            return Ok(());
+        }
+        let res = rx.next_batch(&mut |package, info| {
+            tally.packets += 1;
+            tally.bytes += info.len as u64;
+            match PackageHeader::parse(&options, package) {
+                Some((mut header, payload)) => {
+                    // a tag taken out of the frame was the outer one, of
+                    // the tunnel for a decapsulated packet, which keeps the
+                    // tags of the frame inside
+                    if info.vlan.is_some() {
+                        if header.tunnel.is_none() {
+                            header.inner_vlan = header.vlan;
+                            header.vlan = info.vlan;
+                        } else if header.vlan.is_none() {
+                            header.vlan = info.vlan;
+                        }
+                    }
+                    neighbor::observe(&header, payload, networks, &mut aggregation.neighbors);
+                    names::observe(&header, payload, &mut aggregation.dns);
+                    let segments = hostnames.is_some()
+                        || tcp.is_some()
+                        || export.is_some()
+                        || config.anomalies;
+                    let segment = match (&header.ip_header, segments) {
+                        (Some(ip_header), true) => Segment::new(ip_header, payload),
+                        _ => None,
+                    };
+                    if let Some(segment) = &segment {
+                        if let Some(hostnames) = &mut hostnames {
+                            header.host = hostnames.lookup(segment);
+                        }
+                        if let Some(tcp) = &mut tcp {
+                            tcp.track(segment, info.len);
+                        }
+                        if config.anomalies {
+                            anomaly::observe(segment, &mut aggregation.attempts);
+                        }
+                    }
+                    if let (Some(export), Some(ip_header)) = (&mut export, &header.ip_header) {
+                        // a batch may have waited for its first frame
+                        let timestamp = start_time.elapsed().as_millis() as u64;
+                        let (ports, tcp_flags) = match &segment {
+                            Some(segment) => (
+                                (segment.source.port(), segment.destination.port()),
+                                segment.tcp.get(13).copied().unwrap_or(0),
+                            ),
+                            None => ((0, 0), 0),
+                        };
+                        export.observe(
+                            header.source,
+                            ip_header.source,
+                            ip_header.destination,
+                            ip_header.protocol,
+                            ports,
+                            tcp_flags,
+                            info.len,
+                            timestamp,
+                        );
+                    }
+                    *aggregation.headers.entry(header).or_insert(0) += info.len;
+                }
+                None => tally.unparseable += 1,
+            }
+        });
+        let mut ended = None;
+        match res {
+            Ok(()) => consecutive_errors = 0,
+            // the end of a replayed file
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => ended = Some(Ok(())),
+            Err(e) => {
+                tally.receive_errors += 1;
+                consecutive_errors += 1;
+                // a file with a corrupt record can't be read any further
+                if e.kind() == std::io::ErrorKind::InvalidData
+                    || consecutive_errors >= MAX_CONSECUTIVE_ERRORS
+                {
+                    ended = Some(Err(e));
+                }
+            }
+        }
+        // whatever was counted before the capture ended still goes out
+        if ended.is_none() && flushed.elapsed() < FLUSH_INTERVAL {
+            continue;
+        }
+        flushed = Instant::now();
+        if let Some(hostnames) = &mut hostnames {
+            hostnames.tick();
+        }
+        if let Some(export) = &mut export {
+            export.expire(start_time.elapsed().as_millis() as u64);
+        }
+        if let Some(tcp) = &mut tcp {
+            let updates = tcp.flush();
+            if !updates.is_empty() {
+                aggregation.flows.push(updates);
+            }
+        }
+        if let Some(total) = rx.dropped() {
+            tally.kernel_drops = total.saturating_sub(dropped);
+            dropped = total;
+        }
+        counters.add(&std::mem::take(&mut tally));
+        if aggregation.is_empty() {
+            match ended {
+                Some(res) => return res,
+                None => continue,
+            }
+        }
+        let capacity = aggregation.headers.len();
+        let full = std::mem::replace(
+            &mut aggregation,
+            Aggregation {
+                headers: HashMap::with_capacity(capacity),
+                neighbors: HashMap::new(),
+                dns: HashMap::new(),
+                flows: Vec::new(),
+                attempts: HashMap::new(),
+            },
+        );
+        if let Some(res) = ended {
+            // nothing comes after the last interval to carry it, so wait
+            // for the ticker, which drains the channel every second
+            if futures::executor::block_on(tx.send(full)).is_err() {
+                tally.backpressure += 1;
+            }
+            counters.add(&tally);
+            return res;
+        }
+        match tx.try_send(full) {
+            Ok(()) => {}
+            // the ticker is behind, keep counting into the same totals
+            Err(e) if e.is_full() => {
+                tally.backpressure += 1;
+                aggregation = e.into_inner();
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+}
+
+/// The newest history entry of an interface, one tick's worth of traffic.
+pub struct LastTick {
+    pub bytes: u64,
+    /// Bytes sent or received per IP address.
+    pub hosts: HashMap<IpAddr, u64>,
+    /// Source MAC addresses.
+    pub macs: HashSet<MacAddr>,
+    pub listening: bool,
+}
+
+pub struct InterfaceStatistics {
+    buffer: Option<mpsc::Receiver<Aggregation>>,
+    history: VecDeque<(u64, HashMap<PackageHeader, usize>)>,
+    closed: (
+        futures::future::Shared<oneshot::Receiver<()>>,
+        Option<oneshot::Sender<()>>,
+    ),
+    mac: Option<MacAddr>,
+    counters: Arc<CaptureCounters>,
+    neighbors: NeighborTable,
+    names: NameCache,
+    flows: FlowTable,
+    anomalies: Option<AnomalyDetector>,
+    /// What the kernel counted in the last second, to compare the capture
+    /// with.
+    kernel: KernelSample,
+    /// The configuration of a persistent capture, to listen again with once
+    /// the interface is back.
+    resume: Option<CaptureConfig>,
+    /// The exporter interface a collector feeds, `None` for captures.
+    remote: Option<RemoteSource>,
+}
+
+impl InterfaceStatistics {
+    /// Whether the kernel counters are compared with the capture.
+    fn samples_kernel(&self) -> bool {
+        self.closed.1.is_some() && self.remote.is_none()
+    }
+
+    fn update(&mut self, timestamp: u128, history_length_limit: usize) {
+        let listening = self.closed.1.is_some();
+        // what a capture sent before it ended still makes one last tick
+        if !listening && self.buffer.is_none() {
+            return;
+        }
+        let mut buffer = Aggregation::default();
+        if let Some(rx) = &mut self.buffer {
+            while let Ok(Some(aggregation)) = rx.try_next() {
+                if buffer.is_empty() {
+                    buffer = aggregation;
+                    continue;
+                }
+                buffer.merge(aggregation);
+            }
+        }
+        if !listening {
+            self.buffer = None;
+        }
+        self.neighbors.update(timestamp as u64, buffer.neighbors);
+        self.names.update(timestamp as u64, buffer.dns);
+        for updates in buffer.flows {
+            self.flows.update(updates);
+        }
+        self.history.push_back((timestamp as u64, buffer.headers));
+        if let (Some(detector), Some((_, headers))) = (&mut self.anomalies, self.history.back()) {
+            detector.update(timestamp as u64, headers, &buffer.attempts);
+        }
+        let len = self.history.len();
+        if history_length_limit < len {
+            for _ in 0..(len - history_length_limit) {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    pub fn counters(&self) -> &CaptureCounters {
+        &self.counters
+    }
+
+    pub fn last_tick(&self) -> LastTick {
+        let mut tick = LastTick {
+            bytes: 0,
+            hosts: HashMap::new(),
+            macs: HashSet::new(),
+            listening: self.closed.1.is_some(),
+        };
+        // the history of a stopped interface keeps its last second, which
+        // must not look like traffic to the rules
+        if !tick.listening {
+            return tick;
+        }
+        if let Some((_, m)) = self.history.back() {
+            for (header, &len) in m.iter() {
+                let len = len as u64;
+                tick.bytes += len;
+                if let Some(ip_header) = &header.ip_header {
+                    *tick.hosts.entry(ip_header.source).or_insert(0) += len;
+                    if ip_header.destination != ip_header.source {
+                        *tick.hosts.entry(ip_header.destination).or_insert(0) += len;
+                    }
+                }
+                tick.macs.extend(header.source);
+            }
+        }
+        tick
+    }
+
+    /// The history summed up by server name, flows without one are left out.
+    pub fn hosts_to_json(&self) -> Value {
+        let history: Vec<(u64, HashMap<&str, usize>)> = self
+            .history
+            .iter()
+            .map(|(timestamp, m)| {
+                let mut hosts = HashMap::new();
+                for (header, len) in m.iter() {
+                    if let Some(host) = &header.host {
+                        *hosts.entry(host.as_ref()).or_insert(0) += len;
+                    }
+                }
+                (*timestamp, hosts)
+            })
+            .collect();
+        json!(history)
+    }
+
+    pub fn active_flows_to_json(&self) -> Value {
+        self.flows.active_to_json()
+    }
+
+    pub fn closed_flows_to_json(&self) -> Value {
+        self.flows.closed_to_json()
+    }
+
+    pub fn anomalies_to_json(&self) -> Value {
+        match &self.anomalies {
+            Some(detector) => detector.to_json(),
+            None => json!([]),
+        }
+    }
+
+    pub fn neighbors_to_json(&self, vendors: &Vendors) -> Value {
+        self.neighbors.to_json(vendors)
+    }
+
+    /// The configuration to listen again with, for persistent captures which
+    /// ended on their own.
+    pub fn resumable(&self) -> Option<CaptureConfig> {
+        match (&self.resume, &self.remote) {
+            (Some(config), None) if self.closed.1.is_none() => Some(config.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.closed.1.is_some()
+    }
+
+    /// Stop listening on request, persistent captures are not resumed.
+    pub fn stop(&mut self) {
+        self.resume = None;
+        self.close();
+    }
+
+    pub fn close(&mut self) {
+        let mut tx = None;
+        std::mem::swap(&mut tx, &mut self.closed.1);
+        // the capture thread stops once it notices the channel is closed,
+        // what it sent until then is taken on the next tick
+        if let Some(rx) = &mut self.buffer {
+            rx.close();
+        }
+        self.flows.clear_active();
+        // just drop tx
+        // if let Some(tx) = tx {
+        //     let _ = tx.send(());
+        // }
+    }
+
+    fn convert_map(map: &HashMap<PackageHeader, usize>) -> HashMap<String, usize> {
+        map.iter()
+            .map(|(key, value)| (json!(key).to_string(), *value))
+            .collect()
+    }
+
+    /// Vendors of the MAC addresses and names of the IP addresses in the
+    /// history entries after `since`.
+    fn annotations(
+        &self,
+        since: Option<u64>,
+        vendors: &Vendors,
+        reverse_dns: Option<&ReverseDns>,
+    ) -> (Value, Value) {
+        let mut macs: HashSet<MacAddr> = self.mac.into_iter().collect();
+        let mut ips: HashSet<IpAddr> = HashSet::new();
+        for (_, m) in self
+            .history
+            .iter()
+            .filter(|(timestamp, _)| since.is_none_or(|since| *timestamp > since))
+        {
+            for header in m.keys() {
+                macs.extend([header.source, header.destination].into_iter().flatten());
+                if let Some(ip_header) = &header.ip_header {
+                    ips.insert(ip_header.source);
+                    ips.insert(ip_header.destination);
+                }
+            }
+        }
+        let mut names = serde_json::Map::new();
+        for ip in ips {
+            let name = match self.names.get(&ip) {
+                Some(name) => Some(name.to_string()),
+                None => reverse_dns.and_then(|reverse_dns| reverse_dns.lookup(ip)),
+            };
+            if let Some(name) = name {
+                names.insert(ip.to_string(), json!(name));
+            }
+        }
+        (vendors.annotate(macs), json!(names))
+    }
+
+    pub async fn to_json(&self, vendors: &Vendors, reverse_dns: Option<&ReverseDns>) -> Value {
+        let closed = self.closed.1.is_none();
+        let history: Vec<(&u64, HashMap<String, usize>)> = self
+            .history
+            .iter()
+            .map(|(t, m)| (t, Self::convert_map(m)))
+            .collect();
+        let (vendors, names) = self.annotations(None, vendors, reverse_dns);
+        json!({
+            "history": history,
+            "closed": closed,
+            "mac": self.mac,
+            "remote": self.remote,
+            "persistent": self.resume.is_some(),
+            "kernel": self.kernel.rate,
+            "counters": self.counters.to_json(),
+            "vendors": vendors,
+            "names": names,
+        })
+    }
+
+    pub async fn part_to_json(
+        &self,
+        timestamp_limit: u64,
+        vendors: &Vendors,
+        reverse_dns: Option<&ReverseDns>,
+    ) -> Value {
+        if !self.history.is_empty() && self.history[self.history.len() - 1].0 < timestamp_limit {
+            return self.to_json(vendors, reverse_dns).await;
+        }
+
+        let closed = self.closed.1.is_none();
+        let mut v = Vec::with_capacity(self.history.len());
+        let mut i = self.history.iter();
+        while let Some((timestamp, value)) = i.next() {
+            if *timestamp <= timestamp_limit {
+                v.push(None);
+            } else {
+                v.push(Some((timestamp, json!(Self::convert_map(value)))));
+                for (timestamp, value) in i.by_ref() {
+                    v.push(Some((timestamp, json!(Self::convert_map(value)))));
+                }
+                break;
+            }
+        }
+
+        let (vendors, names) = self.annotations(Some(timestamp_limit), vendors, reverse_dns);
+        json!({
+            "history": v,
+            "closed": closed,
+            "remote": self.remote,
+            "persistent": self.resume.is_some(),
+            "kernel": self.kernel.rate,
+            "counters": self.counters.to_json(),
+            "vendors": vendors,
+            "names": names,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::StreamExt;
+
+    use std::io;
+    use std::net::Ipv4Addr;
+
+    use crate::capture::LinkType;
+
+    /// Hands out its frames once, then stays idle like a quiet interface.
+    struct FakeSource {
+        frames: VecDeque<Vec<u8>>,
+        current: Vec<u8>,
+    }
+
+    impl CaptureSource for FakeSource {
+        fn next(&mut self) -> io::Result<Option<&[u8]>> {
+            match self.frames.pop_front() {
+                Some(frame) => {
+                    self.current = frame;
+                    Ok(Some(&self.current))
+                }
+                None => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    Ok(None)
+                }
+            }
+        }
+
+        fn link_type(&self) -> LinkType {
+            LinkType::Ethernet
+        }
+    }
+
+    const SOURCE_MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const DESTINATION_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+
+    /// An Ethernet frame of `len` bytes with an IPv4 UDP packet.
+    fn udp_frame(source: Ipv4Addr, destination: Ipv4Addr, len: usize) -> Vec<u8> {
+        let mut frame = vec![0; len];
+        frame[0..6].copy_from_slice(&DESTINATION_MAC);
+        frame[6..12].copy_from_slice(&SOURCE_MAC);
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        let ip = &mut frame[14..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((len - 14) as u16).to_be_bytes());
+        ip[8] = 64;
+        ip[9] = 17;
+        ip[12..16].copy_from_slice(&source.octets());
+        ip[16..20].copy_from_slice(&destination.octets());
+        let udp = &mut ip[20..];
+        udp[0..2].copy_from_slice(&40000u16.to_be_bytes());
+        udp[2..4].copy_from_slice(&53u16.to_be_bytes());
+        udp[4..6].copy_from_slice(&((len - 34) as u16).to_be_bytes());
+        frame
+    }
+
+    fn udp_header(source: Ipv4Addr, destination: Ipv4Addr) -> PackageHeader {
+        PackageHeader {
+            protocol: 0x0800,
+            source: Some(MacAddr::from(SOURCE_MAC)),
+            destination: Some(MacAddr::from(DESTINATION_MAC)),
+            ip_header: Some(header::IpHeader {
+                source: source.into(),
+                destination: destination.into(),
+                protocol: 17,
+                fragment: false,
+            }),
+            vlan: None,
+            inner_vlan: None,
+            mpls_label: None,
+            tunnel: None,
+            host: None,
+        }
+    }
+
+    #[test]
+    fn capture_loop_aggregates_bytes_per_header() {
+        let a = Ipv4Addr::new(192, 168, 1, 10);
+        let b = Ipv4Addr::new(192, 168, 1, 20);
+        let c = Ipv4Addr::new(10, 0, 0, 1);
+        let source = FakeSource {
+            frames: vec![
+                udp_frame(a, b, 100),
+                udp_frame(c, b, 60),
+                udp_frame(a, b, 120),
+            ]
+            .into(),
+            current: Vec::new(),
+        };
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let counters = Arc::new(CaptureCounters::default());
+        let capture = {
+            let counters = counters.clone();
+            std::thread::spawn(move || {
+                capture_loop(
+                    Box::new(source),
+                    tx,
+                    &counters,
+                    &CaptureConfig::default(),
+                    &[],
+                    Instant::now(),
+                    None,
+                )
+            })
+        };
+
+        let mut headers: HashMap<PackageHeader, usize> = HashMap::new();
+        while headers.values().sum::<usize>() < 280 {
+            let aggregation = futures::executor::block_on(rx.next()).expect("capture ended");
+            for (header, bytes) in aggregation.headers {
+                *headers.entry(header).or_insert(0) += bytes;
+            }
+        }
+        drop(rx);
+        capture.join().unwrap().unwrap();
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get(&udp_header(a, b)), Some(&220));
+        assert_eq!(headers.get(&udp_header(c, b)), Some(&60));
+        let totals = counters.to_json();
+        assert_eq!(totals["packets"], 3);
+        assert_eq!(totals["bytes"], 280);
+        assert_eq!(totals["unparseable"], 0);
+    }
+
+    /// A pcap file of two frames an hour apart.
+    fn write_pcap_with_gap(path: &std::path::Path) {
+        let frame = udp_frame(
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(192, 168, 1, 20),
+            60,
+        );
+        let mut file = 0xa1b2c3d4u32.to_le_bytes().to_vec();
+        // version 2.4, then time zone, accuracy, snapshot length, Ethernet
+        file.extend_from_slice(&[2, 0, 4, 0]);
+        for value in [0u32, 0, 65535, 1] {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+        for seconds in [0u32, 3600] {
+            for value in [seconds, 0, frame.len() as u32, frame.len() as u32] {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+            file.extend_from_slice(&frame);
+        }
+        std::fs::write(path, file).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stopped_captures_end_within_a_read_timeout() {
+        let path =
+            std::env::temp_dir().join(format!("network_view-gap-{}.pcap", std::process::id()));
+        write_pcap_with_gap(&path);
+        let config = CaptureConfig {
+            backend: Some(Backend::PcapFile),
+            file: Some(path.to_string_lossy().into_owned()),
+            realtime: true,
+            ..Default::default()
+        };
+        for _ in 0..20 {
+            let (closed_tx, closed) = oneshot::channel::<()>();
+            let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+            let capture = tokio::spawn({
+                let config = config.clone();
+                async move {
+                    statistics_interface(
+                        "gap",
+                        None,
+                        &config,
+                        Backend::PcapFile,
+                        Instant::now(),
+                        None,
+                        tx,
+                        Default::default(),
+                        closed.shared(),
+                    )
+                    .await
+                }
+            });
+            // waiting for the frame an hour later
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            // as InterfaceStatistics::close does
+            drop(closed_tx);
+            rx.close();
+            // the blocking thread and its file are gone once this returns,
+            // give scheduling some slack on top of the read timeout
+            tokio::time::timeout(capture::READ_TIMEOUT * 2, capture)
+                .await
+                .expect("capture outlived its read timeout")
+                .unwrap();
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
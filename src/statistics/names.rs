@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::header::PackageHeader;
+use crate::dns::{RecordData, Response};
+
+const IP_PROTOCOL_UDP: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+const DNS_PORT: u16 = 53;
+const MDNS_PORT: u16 = 5353;
+
+/// Forget the longest unused names beyond this many per interface.
+const MAX_NAMES: usize = 16384;
+
+/// Addresses and the names they were resolved from between two flushes.
+pub type DnsAnswers = HashMap<IpAddr, String>;
+
+/// Learn names from DNS and multicast DNS responses over UDP. Addresses are
+/// named after the question, so a CDN address answering through a CNAME
+/// chain carries the name the client asked for.
+pub fn observe(header: &PackageHeader, payload: &[u8], answers: &mut DnsAnswers) {
+    match &header.ip_header {
+        Some(ip_header) if ip_header.protocol == IP_PROTOCOL_UDP => {}
+        _ => return,
+    }
+    let source_port = match payload.get(..2) {
+        Some(port) => u16::from_be_bytes([port[0], port[1]]),
+        None => return,
+    };
+    if source_port != DNS_PORT && source_port != MDNS_PORT {
+        return;
+    }
+    let response = match payload.get(UDP_HEADER_LEN..).and_then(Response::parse) {
+        Some(response) => response,
+        None => return,
+    };
+    for record in response.answers {
+        if let RecordData::Address(ip) = record.data {
+            let name = match &response.question {
+                Some(question) => question.clone(),
+                None => record.name,
+            };
+            answers.insert(ip, name);
+        }
+    }
+}
+
+/// Names from passive DNS, kept across stop/listen cycles. Answers are kept
+/// past their TTL since connections outlive it.
+#[derive(Default)]
+pub struct NameCache {
+    names: HashMap<IpAddr, (String, u64)>,
+}
+
+impl NameCache {
+    pub fn update(&mut self, timestamp: u64, answers: DnsAnswers) {
+        for (ip, name) in answers {
+            self.names.insert(ip, (name, timestamp));
+        }
+        if self.names.len() > MAX_NAMES {
+            let mut seen: Vec<u64> = self.names.values().map(|(_, t)| *t).collect();
+            let mut excess = self.names.len() - MAX_NAMES;
+            let (_, &mut threshold, _) = seen.select_nth_unstable(excess - 1);
+            self.names.retain(|_, (_, t)| {
+                if excess > 0 && *t <= threshold {
+                    excess -= 1;
+                    return false;
+                }
+                true
+            });
+        }
+    }
+
+    pub fn get(&self, ip: &IpAddr) -> Option<&str> {
+        self.names.get(ip).map(|(name, _)| name.as_str())
+    }
+}
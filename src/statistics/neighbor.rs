@@ -0,0 +1,183 @@
+use pnet::ipnetwork::IpNetwork;
+use pnet::packet::arp::ArpPacket;
+use pnet::util::MacAddr;
+use serde_json::{json, Value};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, Ipv6Addr};
+
+use super::header::PackageHeader;
+use crate::vendor::Vendors;
+
+const ETHERTYPE_ARP: u16 = 0x0806;
+const IP_PROTOCOL_ICMPV6: u8 = 58;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_ADDRESS: u8 = 2;
+
+/// Forget the longest unseen neighbors beyond this many per interface.
+const MAX_NEIGHBORS: usize = 4096;
+/// Addresses kept per neighbor, a router answering for a whole subnet
+/// through proxy ARP would otherwise grow without limit.
+const MAX_ADDRESSES: usize = 64;
+
+/// MAC to IP pairs the capture thread saw between two flushes.
+pub type Sightings = HashMap<MacAddr, HashSet<IpAddr>>;
+
+/// Learn neighbors from ARP, from NDP and from the source of IP packets
+/// sent within one of the interface's own networks. Packets from further
+/// away carry the router's MAC, so their addresses are not attributed.
+pub fn observe(
+    header: &PackageHeader,
+    payload: &[u8],
+    networks: &[IpNetwork],
+    sightings: &mut Sightings,
+) {
+    if header.tunnel.is_some() {
+        return;
+    }
+    let source = match header.source {
+        Some(mac) if is_unicast(mac) => mac,
+        _ => return,
+    };
+    if header.protocol == ETHERTYPE_ARP {
+        if let Some(arp) = ArpPacket::new(payload) {
+            let ip = arp.get_sender_proto_addr();
+            if !ip.is_unspecified() {
+                see(sightings, arp.get_sender_hw_addr(), IpAddr::V4(ip));
+            }
+        }
+        return;
+    }
+    let ip_header = match &header.ip_header {
+        Some(ip_header) => ip_header,
+        None => return,
+    };
+    if ip_header.protocol == IP_PROTOCOL_ICMPV6 {
+        if let IpAddr::V6(ip) = ip_header.source {
+            observe_ndp(ip, payload, sightings);
+        }
+    }
+    let ip = ip_header.source;
+    let on_link = match ip {
+        IpAddr::V4(v4) if v4.is_unspecified() => false,
+        IpAddr::V4(v4) => v4.is_link_local() || networks.iter().any(|n| n.contains(ip)),
+        IpAddr::V6(v6) => is_link_local(v6) || networks.iter().any(|n| n.contains(ip)),
+    };
+    if on_link {
+        see(sightings, source, ip);
+    }
+}
+
+fn observe_ndp(source: Ipv6Addr, icmp: &[u8], sightings: &mut Sightings) {
+    let (options, ip, wanted) = match icmp.first() {
+        Some(&ROUTER_ADVERTISEMENT) => (icmp.get(16..), source, OPTION_SOURCE_LINK_ADDRESS),
+        Some(&NEIGHBOR_SOLICITATION) => (icmp.get(24..), source, OPTION_SOURCE_LINK_ADDRESS),
+        Some(&NEIGHBOR_ADVERTISEMENT) => match icmp.get(8..24) {
+            Some(target) => {
+                let target: [u8; 16] = target.try_into().unwrap();
+                (icmp.get(24..), target.into(), OPTION_TARGET_LINK_ADDRESS)
+            }
+            None => return,
+        },
+        _ => return,
+    };
+    // duplicate address detection solicits from the unspecified address
+    if ip.is_unspecified() {
+        return;
+    }
+    let mut options = match options {
+        Some(options) => options,
+        None => return,
+    };
+    // type, length in units of 8 bytes, data
+    while options.len() >= 8 {
+        let len = options[1] as usize * 8;
+        if len == 0 || len > options.len() {
+            return;
+        }
+        if options[0] == wanted {
+            let m = &options[2..8];
+            let mac = MacAddr::new(m[0], m[1], m[2], m[3], m[4], m[5]);
+            if is_unicast(mac) {
+                see(sightings, mac, IpAddr::V6(ip));
+            }
+            return;
+        }
+        options = &options[len..];
+    }
+}
+
+fn see(sightings: &mut Sightings, mac: MacAddr, ip: IpAddr) {
+    sightings.entry(mac).or_default().insert(ip);
+}
+
+fn is_unicast(mac: MacAddr) -> bool {
+    mac.0 & 0x01 == 0 && mac != MacAddr::zero()
+}
+
+fn is_link_local(ip: Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+struct Neighbor {
+    first_seen: u64,
+    last_seen: u64,
+    ips: BTreeSet<IpAddr>,
+}
+
+/// Hosts seen on the link of one interface, kept across stop/listen cycles.
+#[derive(Default)]
+pub struct NeighborTable {
+    neighbors: HashMap<MacAddr, Neighbor>,
+}
+
+impl NeighborTable {
+    pub fn update(&mut self, timestamp: u64, sightings: Sightings) {
+        for (mac, ips) in sightings {
+            let neighbor = self.neighbors.entry(mac).or_insert_with(|| Neighbor {
+                first_seen: timestamp,
+                last_seen: timestamp,
+                ips: BTreeSet::new(),
+            });
+            neighbor.last_seen = timestamp;
+            for ip in ips {
+                if neighbor.ips.len() >= MAX_ADDRESSES {
+                    break;
+                }
+                neighbor.ips.insert(ip);
+            }
+        }
+        if self.neighbors.len() > MAX_NEIGHBORS {
+            let mut last_seen: Vec<u64> = self.neighbors.values().map(|n| n.last_seen).collect();
+            let mut excess = self.neighbors.len() - MAX_NEIGHBORS;
+            let (_, &mut threshold, _) = last_seen.select_nth_unstable(excess - 1);
+            self.neighbors.retain(|_, n| {
+                if excess > 0 && n.last_seen <= threshold {
+                    excess -= 1;
+                    return false;
+                }
+                true
+            });
+        }
+    }
+
+    pub fn to_json(&self, vendors: &Vendors) -> Value {
+        let neighbors: Vec<Value> = self
+            .neighbors
+            .iter()
+            .map(|(mac, neighbor)| {
+                json!({
+                    "mac": mac,
+                    "first_seen": neighbor.first_seen,
+                    "last_seen": neighbor.last_seen,
+                    "ips": neighbor.ips,
+                    "vendor": vendors.lookup(*mac),
+                })
+            })
+            .collect();
+        json!(neighbors)
+    }
+}
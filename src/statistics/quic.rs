@@ -0,0 +1,160 @@
+//! Decrypts the client's QUIC version 1 Initial packet (RFC 9001 section 5),
+//! whose keys derive from the connection ID alone, to read the server name
+//! of the ClientHello it carries.
+
+use ring::aead::{self, quic::HeaderProtectionKey, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::hkdf::{KeyType, Prk, Salt, HKDF_SHA256};
+
+use super::inspect::client_hello_server_name;
+
+const VERSION_1: u32 = 1;
+const INITIAL_SALT: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+const SAMPLE_LEN: usize = 16;
+const FRAME_PADDING: u64 = 0x00;
+const FRAME_PING: u64 = 0x01;
+const FRAME_ACK: u64 = 0x02;
+const FRAME_ACK_ECN: u64 = 0x03;
+const FRAME_CRYPTO: u64 = 0x06;
+
+struct Len(usize);
+
+impl KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// HKDF-Expand-Label of TLS 1.3 with an empty context.
+fn expand_label(secret: &Prk, label: &[u8], out: &mut [u8]) -> Option<()> {
+    let len = (out.len() as u16).to_be_bytes();
+    let label_len = [(b"tls13 ".len() + label.len()) as u8];
+    let info: [&[u8]; 5] = [&len, &label_len, b"tls13 ", label, &[0]];
+    secret.expand(&info, Len(out.len())).ok()?.fill(out).ok()
+}
+
+pub fn server_name(datagram: &[u8]) -> Option<String> {
+    let first = *datagram.first()?;
+    // long header, fixed bit, Initial
+    if first & 0xc0 != 0xc0 || (first >> 4) & 0x03 != 0 {
+        return None;
+    }
+    let version = u32::from_be_bytes(datagram.get(1..5)?.try_into().ok()?);
+    if version != VERSION_1 {
+        return None;
+    }
+    let mut position = 5;
+    let dcid_len = *datagram.get(position)? as usize;
+    let dcid = datagram.get(position + 1..position + 1 + dcid_len)?;
+    position += 1 + dcid_len;
+    let scid_len = *datagram.get(position)? as usize;
+    position += 1 + scid_len;
+    let token_len = length(datagram, &mut position)?;
+    position = position.checked_add(token_len)?;
+    let len = length(datagram, &mut position)?;
+    let pn_offset = position;
+    let packet = datagram.get(..pn_offset.checked_add(len)?)?;
+
+    let mut client_secret = [0u8; 32];
+    let initial = Salt::new(HKDF_SHA256, &INITIAL_SALT).extract(dcid);
+    expand_label(&initial, b"client in", &mut client_secret)?;
+    let client = Prk::new_less_safe(HKDF_SHA256, &client_secret);
+    let (mut key, mut iv, mut hp) = ([0u8; 16], [0u8; 12], [0u8; 16]);
+    expand_label(&client, b"quic key", &mut key)?;
+    expand_label(&client, b"quic iv", &mut iv)?;
+    expand_label(&client, b"quic hp", &mut hp)?;
+
+    let sample = packet.get(pn_offset + 4..pn_offset + 4 + SAMPLE_LEN)?;
+    let mask = HeaderProtectionKey::new(&aead::quic::AES_128, &hp)
+        .ok()?
+        .new_mask(sample)
+        .ok()?;
+    let mut packet = packet.to_vec();
+    packet[0] ^= mask[0] & 0x0f;
+    let pn_len = (packet[0] & 0x03) as usize + 1;
+    for i in 0..pn_len {
+        packet[pn_offset + i] ^= mask[1 + i];
+        iv[12 - pn_len + i] ^= packet[pn_offset + i];
+    }
+    let (header, payload) = packet.split_at_mut(pn_offset + pn_len);
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).ok()?);
+    let plaintext = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(iv),
+            Aad::from(&*header),
+            payload,
+        )
+        .ok()?;
+    client_hello_server_name(&crypto_stream(plaintext)?)
+}
+
+/// The start of the CRYPTO stream in the frames of one packet. Browsers
+/// split the ClientHello into several frames in shuffled order; a
+/// ClientHello continued in the next packet is read as far as it goes.
+fn crypto_stream(frames: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut position = 0;
+    while position < frames.len() {
+        match varint(frames, &mut position)? {
+            FRAME_PADDING | FRAME_PING => {}
+            kind @ (FRAME_ACK | FRAME_ACK_ECN) => {
+                varint(frames, &mut position)?; // largest acknowledged
+                varint(frames, &mut position)?; // delay
+                let ranges = varint(frames, &mut position)?;
+                varint(frames, &mut position)?; // first range
+                for _ in 0..ranges * 2 {
+                    varint(frames, &mut position)?;
+                }
+                if kind == FRAME_ACK_ECN {
+                    for _ in 0..3 {
+                        varint(frames, &mut position)?;
+                    }
+                }
+            }
+            FRAME_CRYPTO => {
+                let offset = length(frames, &mut position)?;
+                let len = length(frames, &mut position)?;
+                let end = position.checked_add(len)?;
+                chunks.push((offset, frames.get(position..end)?));
+                position = end;
+            }
+            _ => break,
+        }
+    }
+    chunks.sort_by_key(|(offset, _)| *offset);
+    let mut stream = Vec::new();
+    for (offset, data) in chunks {
+        if offset > stream.len() {
+            break;
+        }
+        if offset + data.len() > stream.len() {
+            stream.extend_from_slice(&data[stream.len() - offset..]);
+        }
+    }
+    if stream.is_empty() {
+        return None;
+    }
+    Some(stream)
+}
+
+/// A variable length integer taken as a length or offset, `None` unless it
+/// fits `usize`. On 32-bit targets a crafted packet has them overflow
+/// otherwise, every sum with one is checked as well.
+fn length(data: &[u8], position: &mut usize) -> Option<usize> {
+    usize::try_from(varint(data, position)?).ok()
+}
+
+/// A variable length integer (RFC 9000 section 16).
+fn varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let first = *data.get(*position)?;
+    let len = 1 << (first >> 6);
+    let bytes = data.get(*position..*position + len)?;
+    *position += len;
+    Some(
+        bytes[1..]
+            .iter()
+            .fold((first & 0x3f) as u64, |n, &b| n << 8 | b as u64),
+    )
+}
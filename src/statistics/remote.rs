@@ -0,0 +1,194 @@
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex;
+use futures::FutureExt;
+use tracing::info;
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::counters::{CaptureCounters, Tally};
+use super::header::{IpHeader, PackageHeader, ParseOptions};
+use super::{Aggregation, InterfaceStatistics, CHANNEL_CAPACITY};
+use crate::capture::LinkType;
+use crate::collector::{FlowSample, RemoteSource, Sample};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+/// Interfaces one collector feeds at most, further exporters or ifIndexes
+/// are ignored.
+const MAX_INTERFACES: usize = 1024;
+
+struct RemoteInterface {
+    source: RemoteSource,
+    /// `None` while the interface is not listened to.
+    tx: Option<(mpsc::Sender<Aggregation>, Arc<CaptureCounters>)>,
+    aggregation: Aggregation,
+    tally: Tally,
+}
+
+/// The interfaces of remote exporters, fed by a collector in place of a
+/// capture thread. They are listened to as soon as traffic arrives for them,
+/// `not_listen_interfaces` stops accounting until they are listened again.
+pub struct RemoteInterfaces {
+    start_time: Instant,
+    map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>,
+    interfaces: HashMap<(IpAddr, u32), RemoteInterface>,
+}
+
+impl RemoteInterfaces {
+    pub fn new(start_time: Instant, map: Arc<Mutex<HashMap<String, InterfaceStatistics>>>) -> Self {
+        Self {
+            start_time,
+            map,
+            interfaces: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, source: RemoteSource, sample: Sample) {
+        let key = (source.exporter, source.if_index);
+        if !self.interfaces.contains_key(&key) && self.interfaces.len() >= MAX_INTERFACES {
+            return;
+        }
+        let interface = self
+            .interfaces
+            .entry(key)
+            .or_insert_with(|| RemoteInterface {
+                source,
+                tx: None,
+                aggregation: Aggregation::default(),
+                tally: Tally::default(),
+            });
+        let (header, packets, bytes) = match sample {
+            Sample::Frame {
+                frame,
+                raw_ip,
+                packets,
+                bytes,
+            } => {
+                let options = ParseOptions {
+                    link_type: if raw_ip {
+                        LinkType::RawIp
+                    } else {
+                        LinkType::Ethernet
+                    },
+                    decapsulate: false,
+                };
+                (PackageHeader::new(&options, frame), packets, bytes)
+            }
+            Sample::Flow(flow) => {
+                let (packets, bytes) = (flow.packets, flow.bytes);
+                (Some(flow_header(flow)), packets, bytes)
+            }
+        };
+        // sampled counts of a hostile exporter saturate rather than wrap
+        interface.tally.packets = interface.tally.packets.saturating_add(packets);
+        interface.tally.bytes = interface.tally.bytes.saturating_add(bytes);
+        match header {
+            Some(header) => {
+                let total = interface.aggregation.headers.entry(header).or_insert(0);
+                *total = total.saturating_add(bytes as usize);
+            }
+            None => interface.tally.unparseable += 1,
+        }
+    }
+
+    /// Hand the totals since the last flush to the ticker, attaching the
+    /// interfaces which are listened to but not fed yet.
+    pub async fn flush(&mut self) {
+        for interface in self.interfaces.values_mut() {
+            // samples without a header are only tallied
+            if interface.aggregation.is_empty() && interface.tally == Tally::default() {
+                continue;
+            }
+            let aggregation = std::mem::take(&mut interface.aggregation);
+            let tally = std::mem::take(&mut interface.tally);
+            if interface.tx.is_none() {
+                interface.tx = attach(&interface.source, self.start_time, &self.map).await;
+            }
+            let (tx, counters) = match &mut interface.tx {
+                Some(attached) => attached,
+                None => continue,
+            };
+            counters.add(&tally);
+            if aggregation.is_empty() {
+                continue;
+            }
+            match tx.try_send(aggregation) {
+                Ok(()) => {}
+                Err(e) if e.is_full() => {
+                    interface.tally.backpressure += 1;
+                    interface.aggregation = e.into_inner();
+                }
+                // stopped or cleared, attached again with the next traffic
+                Err(_) => interface.tx = None,
+            }
+        }
+    }
+}
+
+/// A channel into the statistics of `source`, registering them on first
+/// sight. `None` while the interface is not listened to.
+async fn attach(
+    source: &RemoteSource,
+    start_time: Instant,
+    map: &Mutex<HashMap<String, InterfaceStatistics>>,
+) -> Option<(mpsc::Sender<Aggregation>, Arc<CaptureCounters>)> {
+    let name = source.name();
+    let mut map = map.lock().await;
+    let elapsed = start_time.elapsed().as_millis() as u64;
+    let (buffer_tx, buffer_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let counters = match map.get_mut(&name) {
+        Some(s) if s.closed.1.is_none() => return None,
+        Some(s) => {
+            s.buffer = Some(buffer_rx);
+            s.counters.clone()
+        }
+        None => {
+            info!(interface = %name, "start collect");
+            let (tx, rx) = oneshot::channel();
+            let statistics = InterfaceStatistics {
+                buffer: Some(buffer_rx),
+                history: VecDeque::from([(elapsed, Default::default())]),
+                closed: (rx.shared(), Some(tx)),
+                mac: None,
+                counters: Default::default(),
+                neighbors: Default::default(),
+                names: Default::default(),
+                flows: Default::default(),
+                anomalies: None,
+                kernel: Default::default(),
+                resume: None,
+                remote: Some(*source),
+            };
+            let counters = statistics.counters.clone();
+            map.insert(name, statistics);
+            counters
+        }
+    };
+    Some((buffer_tx, counters))
+}
+
+fn flow_header(flow: FlowSample) -> PackageHeader {
+    let protocol = match flow.source {
+        IpAddr::V4(_) => ETHERTYPE_IPV4,
+        IpAddr::V6(_) => ETHERTYPE_IPV6,
+    };
+    PackageHeader {
+        protocol,
+        source: flow.source_mac,
+        destination: flow.destination_mac,
+        ip_header: Some(IpHeader {
+            source: flow.source,
+            destination: flow.destination,
+            protocol: flow.protocol,
+            fragment: false,
+        }),
+        vlan: flow.vlan,
+        inner_vlan: None,
+        mpls_label: None,
+        tunnel: None,
+        host: None,
+    }
+}
@@ -0,0 +1,389 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::flow::{FlowKey, Segment, IP_PROTOCOL_TCP};
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const ACK: u8 = 0x10;
+
+/// Connections tracked at once. Beyond that the finished and then the
+/// longest idle ones make room, `EVICTED` at a time.
+const MAX_FLOWS: usize = 65536;
+const EVICTED: usize = MAX_FLOWS / 16;
+/// Gaps in the sequence space remembered per direction, so segments filling
+/// them are told apart from retransmissions.
+const MAX_HOLES: usize = 8;
+/// Idle time after which an established connection is given up on.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Idle time after which a connection which never finished its handshake
+/// is given up on.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Closed connections stay in the table this long, so their last ACKs are
+/// not mistaken for a new connection picked up midstream.
+const LINGER: Duration = Duration::from_secs(2);
+/// Closed connections kept for queries per interface.
+const MAX_CLOSED: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    /// One side sent its FIN.
+    Closing,
+    Closed,
+    Reset,
+    /// Given up on after being idle for too long.
+    Timeout,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FlowRecord {
+    /// The side which sent the SYN, or the one with the higher port for
+    /// connections picked up midstream.
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub state: TcpState,
+    /// Milliseconds since start, like the history timestamps.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub packets: u64,
+    pub bytes_to_server: u64,
+    pub bytes_to_client: u64,
+    /// Milliseconds from the SYN to the ACK of the SYN-ACK, the round trip
+    /// time between client and server through the capture point.
+    pub handshake_rtt: Option<f64>,
+    /// Segments carrying sequence numbers seen before.
+    pub retransmissions: u64,
+    /// Segments arriving after later ones, filling a gap in the sequence
+    /// space rather than repeating data.
+    pub reordered: u64,
+    /// Segments advertising a zero receive window.
+    pub zero_windows: u64,
+}
+
+struct Flow {
+    record: FlowRecord,
+    last_packet: Instant,
+    syn_sent: Option<Instant>,
+    server_isn: Option<u32>,
+    /// Karn's rule: once the SYN or SYN-ACK was sent again it is unknown
+    /// which one was answered, so no round trip time is taken.
+    handshake_retransmitted: bool,
+    /// Sequence number following the highest one seen, client to server
+    /// and server to client.
+    next_seq: [Option<u32>; 2],
+    /// Sequence ranges skipped so far, oldest first.
+    holes: [Vec<(u32, u32)>; 2],
+    fin: [bool; 2],
+    /// Changed since the last flush.
+    dirty: bool,
+    finished: bool,
+}
+
+/// Flow table changes of one flush, applied in order by the ticker.
+#[derive(Default)]
+pub struct FlowUpdates {
+    active: Vec<(FlowKey, FlowRecord)>,
+    closed: Vec<(FlowKey, FlowRecord)>,
+}
+
+impl FlowUpdates {
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty() && self.closed.is_empty()
+    }
+}
+
+/// TCP connection state, kept by the capture thread.
+pub struct TcpTracker {
+    origin: Instant,
+    flows: HashMap<FlowKey, Flow>,
+    closed: Vec<(FlowKey, FlowRecord)>,
+}
+
+impl TcpTracker {
+    /// `origin` is the server start time the timestamps count from.
+    pub fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            flows: HashMap::new(),
+            closed: Vec::new(),
+        }
+    }
+
+    /// Account one segment, `len` is the length of the whole frame.
+    pub fn track(&mut self, segment: &Segment, len: usize) {
+        if segment.key.protocol != IP_PROTOCOL_TCP {
+            return;
+        }
+        let tcp = segment.tcp;
+        let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+        let ack = u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]);
+        let flags = tcp[13];
+        let window = u16::from_be_bytes([tcp[14], tcp[15]]);
+        let now = Instant::now();
+        let timestamp = (now - self.origin).as_millis() as u64;
+
+        let restart =
+            flags & (SYN | ACK) == SYN && self.flows.get(&segment.key).is_some_and(|f| f.finished);
+        if restart {
+            self.flows.remove(&segment.key);
+        }
+        if !self.flows.contains_key(&segment.key) {
+            if self.flows.len() >= MAX_FLOWS {
+                self.evict();
+            }
+            let flow = Flow::new(segment, flags, now, timestamp);
+            self.flows.insert(segment.key, flow);
+        }
+        let flow = self.flows.get_mut(&segment.key).unwrap();
+        let was_finished = flow.finished;
+        let record = &mut flow.record;
+        let direction = if segment.source == record.client {
+            0
+        } else {
+            1
+        };
+        record.last_seen = timestamp;
+        record.packets += 1;
+        if direction == 0 {
+            record.bytes_to_server += len as u64;
+        } else {
+            record.bytes_to_client += len as u64;
+        }
+        flow.last_packet = now;
+
+        // sequence space taken by the segment: data, SYN and FIN
+        let seq_len =
+            segment.data.len() as u32 + (flags & SYN != 0) as u32 + (flags & FIN != 0) as u32;
+        let end = seq.wrapping_add(seq_len);
+        match flow.next_seq[direction] {
+            Some(next) if seq_len > 0 => {
+                let holes = &mut flow.holes[direction];
+                if after(seq, next) {
+                    if holes.len() >= MAX_HOLES {
+                        holes.remove(0);
+                    }
+                    holes.push((next, seq));
+                } else if after(next, seq) {
+                    if fill(holes, seq, end) {
+                        record.reordered += 1;
+                    } else {
+                        record.retransmissions += 1;
+                    }
+                }
+                if after(end, next) {
+                    flow.next_seq[direction] = Some(end);
+                }
+            }
+            Some(_) => {}
+            None => flow.next_seq[direction] = Some(end),
+        }
+        if window == 0 && flags & (SYN | RST) == 0 {
+            record.zero_windows += 1;
+        }
+
+        if flags & RST != 0 {
+            record.state = TcpState::Reset;
+        } else if flags & SYN != 0 {
+            if flags & ACK != 0 && direction == 1 {
+                if flow.server_isn.is_some() {
+                    flow.handshake_retransmitted = true;
+                }
+                flow.server_isn = Some(seq);
+                if record.state == TcpState::SynSent {
+                    record.state = TcpState::SynReceived;
+                }
+            } else if direction == 0 {
+                if flow.syn_sent.is_some() {
+                    flow.handshake_retransmitted = true;
+                }
+                flow.syn_sent = Some(now);
+            }
+        } else if flags & ACK != 0
+            && direction == 0
+            && record.state == TcpState::SynReceived
+            && Some(ack) == flow.server_isn.map(|isn| isn.wrapping_add(1))
+        {
+            record.state = TcpState::Established;
+            if let (Some(syn_sent), false) = (flow.syn_sent, flow.handshake_retransmitted) {
+                record.handshake_rtt = Some((now - syn_sent).as_secs_f64() * 1000.0);
+            }
+        }
+        if flags & FIN != 0 && !matches!(record.state, TcpState::Reset | TcpState::Closed) {
+            flow.fin[direction] = true;
+            record.state = if flow.fin == [true, true] {
+                TcpState::Closed
+            } else {
+                TcpState::Closing
+            };
+        }
+
+        if was_finished {
+            return;
+        }
+        flow.finished = matches!(record.state, TcpState::Closed | TcpState::Reset);
+        if flow.finished {
+            self.closed.push((segment.key, record.clone()));
+        } else {
+            flow.dirty = true;
+        }
+    }
+
+    /// Drops finished connections and then the longest idle ones, which are
+    /// reported as timed out.
+    fn evict(&mut self) {
+        let mut candidates: Vec<(bool, Instant, FlowKey)> = self
+            .flows
+            .iter()
+            .map(|(key, flow)| (!flow.finished, flow.last_packet, *key))
+            .collect();
+        let count = EVICTED.min(candidates.len());
+        if count < candidates.len() {
+            candidates.select_nth_unstable_by_key(count, |&(active, seen, _)| (active, seen));
+        }
+        for (_, _, key) in &candidates[..count] {
+            let flow = self.flows.remove(key).unwrap();
+            if !flow.finished {
+                let mut record = flow.record;
+                record.state = TcpState::Timeout;
+                self.closed.push((*key, record));
+            }
+        }
+    }
+
+    /// The changes since the last flush, idle connections time out here.
+    pub fn flush(&mut self) -> FlowUpdates {
+        let now = Instant::now();
+        let mut updates = FlowUpdates {
+            active: Vec::new(),
+            closed: std::mem::take(&mut self.closed),
+        };
+        self.flows.retain(|key, flow| {
+            let idle = now - flow.last_packet;
+            if flow.finished {
+                return idle < LINGER;
+            }
+            let timeout = match flow.record.state {
+                TcpState::SynSent | TcpState::SynReceived => HANDSHAKE_TIMEOUT,
+                _ => IDLE_TIMEOUT,
+            };
+            if idle >= timeout {
+                let mut record = flow.record.clone();
+                record.state = TcpState::Timeout;
+                updates.closed.push((*key, record));
+                return false;
+            }
+            if flow.dirty {
+                flow.dirty = false;
+                updates.active.push((*key, flow.record.clone()));
+            }
+            true
+        });
+        updates
+    }
+}
+
+impl Flow {
+    fn new(segment: &Segment, flags: u8, now: Instant, timestamp: u64) -> Self {
+        let (client, server, state) = if flags & (SYN | ACK) == SYN {
+            (segment.source, segment.destination, TcpState::SynSent)
+        } else if flags & (SYN | ACK) == SYN | ACK {
+            (segment.destination, segment.source, TcpState::SynReceived)
+        } else if segment.source.port() >= segment.destination.port() {
+            (segment.source, segment.destination, TcpState::Established)
+        } else {
+            (segment.destination, segment.source, TcpState::Established)
+        };
+        Self {
+            record: FlowRecord {
+                client,
+                server,
+                state,
+                first_seen: timestamp,
+                last_seen: timestamp,
+                packets: 0,
+                bytes_to_server: 0,
+                bytes_to_client: 0,
+                handshake_rtt: None,
+                retransmissions: 0,
+                reordered: 0,
+                zero_windows: 0,
+            },
+            last_packet: now,
+            syn_sent: None,
+            server_isn: None,
+            handshake_retransmitted: false,
+            next_seq: [None, None],
+            holes: [Vec::new(), Vec::new()],
+            fin: [false, false],
+            dirty: false,
+            finished: false,
+        }
+    }
+}
+
+/// Whether sequence number `a` comes after `b`.
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// Takes `seq..end` out of the gap it lies in, if any.
+fn fill(holes: &mut Vec<(u32, u32)>, seq: u32, end: u32) -> bool {
+    let within = |&(start, stop): &(u32, u32)| !after(start, seq) && !after(end, stop);
+    let index = match holes.iter().position(within) {
+        Some(index) => index,
+        None => return false,
+    };
+    let (start, stop) = holes.remove(index);
+    if after(stop, end) {
+        holes.insert(index, (end, stop));
+    }
+    if after(seq, start) {
+        holes.insert(index, (start, seq));
+    }
+    true
+}
+
+/// The ticker's copy of the flow table of one interface.
+#[derive(Default)]
+pub struct FlowTable {
+    active: HashMap<FlowKey, FlowRecord>,
+    closed: VecDeque<FlowRecord>,
+}
+
+impl FlowTable {
+    pub fn update(&mut self, updates: FlowUpdates) {
+        for (key, record) in updates.active {
+            self.active.insert(key, record);
+        }
+        for (key, record) in updates.closed {
+            self.active.remove(&key);
+            self.closed.push_back(record);
+        }
+        while self.closed.len() > MAX_CLOSED {
+            self.closed.pop_front();
+        }
+    }
+
+    /// The capture stopped, nothing more will be learned about the active
+    /// connections.
+    pub fn clear_active(&mut self) {
+        self.active.clear();
+    }
+
+    pub fn active_to_json(&self) -> Value {
+        json!(self.active.values().collect::<Vec<_>>())
+    }
+
+    pub fn closed_to_json(&self) -> Value {
+        json!(self.closed)
+    }
+}
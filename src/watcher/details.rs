@@ -0,0 +1,49 @@
+use pnet::datalink::NetworkInterface;
+use serde_json::{json, Value};
+
+use crate::statistics::KernelCounters;
+
+/// `IFF_RUNNING`, which pnet only reads on Unix.
+pub fn is_running(interface: &NetworkInterface) -> bool {
+    #[cfg(unix)]
+    return interface.is_running();
+    #[cfg(not(unix))]
+    return interface.is_up();
+}
+
+/// An attribute from `/sys/class/net/<name>/`, only there on Linux.
+fn sysfs(name: &str, attribute: &str) -> Option<String> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/{}", name, attribute))
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+/// What `get_interfaces` tells about an interface. MTU, speed (Mbit/s) and
+/// duplex are `null` where sysfs does not expose them, speed and duplex
+/// usually are while the link is down or for virtual interfaces. The
+/// kernel's interface counters are the totals since the interface came up.
+pub fn describe(interface: &NetworkInterface, listening: bool, permitted: bool) -> Value {
+    let name = &interface.name;
+    let mtu = sysfs(name, "mtu").and_then(|s| s.parse::<u32>().ok());
+    let speed = sysfs(name, "speed")
+        .and_then(|s| s.parse::<i64>().ok())
+        .filter(|&speed| speed > 0);
+    let duplex = sysfs(name, "duplex").filter(|s| s == "full" || s == "half");
+    let addresses: Vec<String> = interface.ips.iter().map(|ip| ip.to_string()).collect();
+    json!({
+        "name": name,
+        "index": interface.index,
+        "mac": interface.mac,
+        "up": interface.is_up(),
+        "running": is_running(interface),
+        "loopback": interface.is_loopback(),
+        "point_to_point": interface.is_point_to_point(),
+        "mtu": mtu,
+        "speed": speed,
+        "duplex": duplex,
+        "addresses": addresses,
+        "counters": KernelCounters::read(name).ok(),
+        "listening": listening,
+        "permitted": permitted,
+    })
+}
@@ -0,0 +1,216 @@
+//! Follows network interfaces appearing, disappearing, being renamed and
+//! going up or down, resumes persistent captures and tells the clients.
+
+use pnet::datalink;
+use serde::Serialize;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use std::collections::HashMap;
+
+use crate::statistics::start_statistics_interface;
+use crate::AppContext;
+
+mod details;
+#[cfg(target_os = "linux")]
+mod netlink;
+
+pub use details::describe;
+use details::is_running;
+#[cfg(target_os = "linux")]
+use netlink::LinkNotifications;
+
+/// How often interfaces are listed when link notifications are unavailable.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Events buffered per client, a client which falls behind skips the oldest.
+pub const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InterfaceEvent {
+    Added {
+        name: String,
+        index: u32,
+        up: bool,
+        running: bool,
+    },
+    Removed {
+        name: String,
+        index: u32,
+    },
+    /// Captures keep following the renamed interface under their old name,
+    /// persistent captures which ended move to the new one.
+    Renamed {
+        name: String,
+        index: u32,
+        from: String,
+    },
+    Changed {
+        name: String,
+        index: u32,
+        up: bool,
+        running: bool,
+    },
+    /// A persistent capture listens again.
+    Resumed {
+        name: String,
+    },
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Link {
+    name: String,
+    up: bool,
+    running: bool,
+}
+
+/// The interfaces by index, which survives renames.
+fn list_links() -> HashMap<u32, Link> {
+    datalink::interfaces()
+        .into_iter()
+        .map(|interface| {
+            let link = Link {
+                name: interface.name.clone(),
+                up: interface.is_up(),
+                running: is_running(&interface),
+            };
+            (interface.index, link)
+        })
+        .collect()
+}
+
+fn diff(old: &HashMap<u32, Link>, new: &HashMap<u32, Link>) -> Vec<InterfaceEvent> {
+    let mut events = Vec::new();
+    for (&index, link) in old {
+        if !new.contains_key(&index) {
+            events.push(InterfaceEvent::Removed {
+                name: link.name.clone(),
+                index,
+            });
+        }
+    }
+    for (&index, link) in new {
+        let old = match old.get(&index) {
+            Some(old) => old,
+            None => {
+                events.push(InterfaceEvent::Added {
+                    name: link.name.clone(),
+                    index,
+                    up: link.up,
+                    running: link.running,
+                });
+                continue;
+            }
+        };
+        if old.name != link.name {
+            events.push(InterfaceEvent::Renamed {
+                name: link.name.clone(),
+                index,
+                from: old.name.clone(),
+            });
+        }
+        if (old.up, old.running) != (link.up, link.running) {
+            events.push(InterfaceEvent::Changed {
+                name: link.name.clone(),
+                index,
+                up: link.up,
+                running: link.running,
+            });
+        }
+    }
+    events
+}
+
+pub async fn run(context: AppContext) {
+    let mut links = list_links();
+    #[cfg(target_os = "linux")]
+    let mut notifications = match LinkNotifications::open() {
+        Ok(notifications) => Some(notifications),
+        Err(e) => {
+            warn!(error = ?e, "Failed to subscribe to link notifications, polling interfaces instead");
+            None
+        }
+    };
+    loop {
+        #[cfg(target_os = "linux")]
+        match &mut notifications {
+            Some(n) => {
+                if let Err(e) = n.wait().await {
+                    warn!(error = ?e, "Link notifications failed, polling interfaces instead");
+                    notifications = None;
+                }
+            }
+            None => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+        #[cfg(not(target_os = "linux"))]
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let current = list_links();
+        let events = diff(&links, &current);
+        links = current;
+        if events.is_empty() {
+            continue;
+        }
+        let mut resumed = Vec::new();
+        {
+            let mut map = context.map.lock().await;
+            for event in &events {
+                match event {
+                    // the capture socket went away with the interface
+                    InterfaceEvent::Removed { name, .. } => {
+                        if let Some(s) = map.get_mut(name) {
+                            if s.is_listening() {
+                                s.close();
+                            }
+                        }
+                    }
+                    // a capture which ended is resumed under the new name, one
+                    // still listening keeps following the interface
+                    InterfaceEvent::Renamed { name, index, from } => {
+                        let moved = match map.get(from) {
+                            Some(s) => s.resumable().is_some() && !map.contains_key(name),
+                            None => false,
+                        };
+                        if moved {
+                            let s = map.remove(from).unwrap();
+                            let config = s.resumable();
+                            map.insert(name.clone(), s);
+                            let up = links.get(index).is_some_and(|link| link.up);
+                            if let (true, Some(config)) = (up, config) {
+                                resumed.push((name.clone(), config));
+                            }
+                        }
+                    }
+                    InterfaceEvent::Added { name, up: true, .. }
+                    | InterfaceEvent::Changed { name, up: true, .. } => {
+                        if resumed.iter().any(|(resumed, _)| resumed == name) {
+                            continue;
+                        }
+                        if let Some(config) = map.get(name).and_then(|s| s.resumable()) {
+                            resumed.push((name.clone(), config));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for event in events {
+            // fails only while no client is connected
+            let _ = context.interface_events.send(event);
+        }
+        for (name, config) in resumed {
+            info!(interface = %name, "resume listen");
+            context.shutdown.spawn(start_statistics_interface(
+                name.clone(),
+                config,
+                context.capture_backend,
+                context.exporter.clone(),
+                context.start_time,
+                context.map.clone(),
+            ));
+            let _ = context
+                .interface_events
+                .send(InterfaceEvent::Resumed { name });
+        }
+    }
+}
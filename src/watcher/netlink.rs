@@ -0,0 +1,106 @@
+use tokio::io::unix::AsyncFd;
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// Multicast group of the link notifications.
+const RTMGRP_LINK: u32 = 1;
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const NLMSG_HDRLEN: usize = 16;
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// A `NETLINK_ROUTE` socket subscribed to `RTM_NEWLINK` and `RTM_DELLINK`.
+pub struct LinkNotifications {
+    fd: AsyncFd<OwnedFd>,
+    buffer: Vec<u8>,
+}
+
+impl LinkNotifications {
+    pub fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = RTMGRP_LINK;
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            buffer: vec![0; BUFFER_SIZE],
+        })
+    }
+
+    /// Waits until a link was added, removed or changed. The burst of
+    /// messages a single change causes is taken as one notification.
+    pub async fn wait(&mut self) -> io::Result<()> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let mut changed = false;
+            loop {
+                let len = unsafe {
+                    libc::recv(
+                        guard.get_inner().as_raw_fd(),
+                        self.buffer.as_mut_ptr() as *mut libc::c_void,
+                        self.buffer.len(),
+                        0,
+                    )
+                };
+                if len >= 0 {
+                    changed |= is_link_change(&self.buffer[..len as usize]);
+                    continue;
+                }
+                let e = io::Error::last_os_error();
+                match e.raw_os_error() {
+                    Some(libc::EAGAIN) => {
+                        guard.clear_ready();
+                        break;
+                    }
+                    // notifications were lost, the interfaces are listed
+                    // again anyway
+                    Some(libc::ENOBUFS) => changed = true,
+                    Some(libc::EINTR) => {}
+                    _ => return Err(e),
+                }
+            }
+            if changed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Whether a datagram carries an `RTM_NEWLINK` or `RTM_DELLINK` message.
+fn is_link_change(datagram: &[u8]) -> bool {
+    let mut offset = 0;
+    while let Some(header) = datagram.get(offset..offset + NLMSG_HDRLEN) {
+        let len = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = u16::from_ne_bytes([header[4], header[5]]);
+        if kind == RTM_NEWLINK || kind == RTM_DELLINK {
+            return true;
+        }
+        if len < NLMSG_HDRLEN {
+            break;
+        }
+        // messages are aligned to four bytes
+        offset += (len + 3) & !3;
+    }
+    false
+}
@@ -4,10 +4,23 @@ use futures::{lock::Mutex, SinkExt, StreamExt};
 use hyper::{body::Incoming, upgrade::Upgraded, Request};
 use hyper_util::rt::TokioIo;
 use pnet::datalink;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
-use crate::{statistics::start_statistics_interface, AppContext};
+use crate::capture::{self, Backend, CaptureConfig};
+use crate::{statistics::start_statistics_interface, watcher, AppContext};
+
+/// `listen_interfaces` takes either a bare interface name or this object.
+#[derive(Deserialize)]
+struct ListenRequest {
+    name: String,
+    #[serde(flatten)]
+    config: CaptureConfig,
+}
 
 pub async fn on_websocket(
     context: &AppContext,
@@ -16,35 +29,65 @@ pub async fn on_websocket(
 ) -> Result<(), Box<dyn Error>> {
     let (tx, rx) = ws.split();
     let tx = Mutex::new(tx);
-    rx.for_each_concurrent(None, |message| async {
+    let mut events = context.interface_events.subscribe();
+    let requests = rx.for_each_concurrent(None, |message| async {
         if let Ok(message) = message {
             let message = match message {
                 tokio_tungstenite::tungstenite::Message::Text(s) => s,
                 _ => return,
             };
 
-            if let Ok(v) = serde_json::from_str::<Value>(&message) {
-                if let Value::Object(mut m) = v {
-                    let tag = m.remove("tag");
-                    let request = m.remove("request");
-                    if let Some(request) = request {
-                        let response = handle_request(context, request).await;
-                        let _ = tx
-                            .lock()
-                            .await
-                            .send(Message::Text(
-                                json!({"tag": tag, "response": response}).to_string(),
-                            ))
-                            .await;
-                    }
+            if let Ok(Value::Object(mut m)) = serde_json::from_str::<Value>(&message) {
+                let tag = m.remove("tag");
+                let request = m.remove("request");
+                if let Some(request) = request {
+                    let response = handle_request(context, request).await;
+                    let _ = tx
+                        .lock()
+                        .await
+                        .send(Message::Text(
+                            json!({"tag": tag, "response": response}).to_string(),
+                        ))
+                        .await;
                 }
             }
         }
-    })
-    .await;
+    });
+    // interface events are pushed without a tag, besides the responses
+    let events = async {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return futures::future::pending().await,
+            };
+            let message = json!({"event": "interface", "data": event}).to_string();
+            if tx.lock().await.send(Message::Text(message)).await.is_err() {
+                return;
+            }
+        }
+    };
+    let shutdown = async {
+        context.shutdown.started().await;
+        let frame = CloseFrame {
+            code: CloseCode::Away,
+            reason: "server shutting down".into(),
+        };
+        let _ = tx.lock().await.send(Message::Close(Some(frame))).await;
+    };
+    futures::pin_mut!(requests, events, shutdown);
+    futures::future::select(requests, futures::future::select(events, shutdown)).await;
     Ok(())
 }
 
+/// Whether interfaces may be listened to with `backend`: the counters need
+/// no privileges, and the capture helper only opens AF_PACKET sockets.
+async fn permitted(context: &AppContext, backend: Backend) -> bool {
+    backend == Backend::Counters
+        || (backend == Backend::AfPacket || !context.privileges.helper)
+            && capture::permitted().await
+}
+
 async fn handle_request(context: &AppContext, request: Value) -> Value {
     match request {
         Value::String(message) => match message.as_str() {
@@ -52,19 +95,62 @@ async fn handle_request(context: &AppContext, request: Value) -> Value {
                 let map = context.map.lock().await;
                 let mut m = serde_json::Map::with_capacity(map.len());
                 for (key, value) in map.iter() {
-                    m.insert(key.clone(), value.to_json().await);
+                    let value = value
+                        .to_json(&context.vendors, context.reverse_dns.as_deref())
+                        .await;
+                    m.insert(key.clone(), value);
                 }
                 return json!(m);
             }
             "get_interfaces" => {
-                let interfaces = datalink::interfaces();
-                let interfaces: Vec<String> = interfaces.into_iter().map(|i| i.name).collect();
+                let permitted = permitted(context, context.capture_backend).await;
+                let map = context.map.lock().await;
+                let interfaces: Vec<Value> = datalink::interfaces()
+                    .iter()
+                    .map(|interface| {
+                        let listening = map.get(&interface.name).is_some_and(|s| s.is_listening());
+                        watcher::describe(interface, listening, permitted)
+                    })
+                    .collect();
                 return json!(interfaces);
             }
+            "get_status" => {
+                return json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "uptime": context.start_time.elapsed().as_millis() as u64,
+                    "privileges": context.privileges,
+                    "capture_permitted": capture::permitted().await,
+                });
+            }
+            "get_hosts" => {
+                let map = context.map.lock().await;
+                let mut m = serde_json::Map::with_capacity(map.len());
+                for (key, value) in map.iter() {
+                    m.insert(key.clone(), value.hosts_to_json());
+                }
+                return json!(m);
+            }
+            "get_anomalies" => {
+                let map = context.map.lock().await;
+                let mut m = serde_json::Map::with_capacity(map.len());
+                for (key, value) in map.iter() {
+                    m.insert(key.clone(), value.anomalies_to_json());
+                }
+                return json!(m);
+            }
+            "get_alerts" => {
+                if let Some(alerts) = &context.alerts {
+                    return alerts.to_json(None);
+                }
+            }
+            "get_neighbors" => {
+                return context.neighbors_to_json().await;
+            }
             _ => {}
         },
         Value::Object(m) => {
-            for (key, value) in m.into_iter() {
+            // one request per object, further keys are ignored
+            if let Some((key, value)) = m.into_iter().next() {
                 match key.as_str() {
                     "get" => {
                         if let Value::Object(m) = value {
@@ -77,21 +163,85 @@ async fn handle_request(context: &AppContext, request: Value) -> Value {
 
                             let map = context.map.lock().await;
                             let mut m = serde_json::Map::with_capacity(map.len());
+                            let reverse_dns = context.reverse_dns.as_deref();
                             for (key, value) in map.iter() {
                                 if let Some(n) = latest_timestamp.get(key) {
-                                    m.insert(key.clone(), value.part_to_json(n.clone()).await);
+                                    let value =
+                                        value.part_to_json(*n, &context.vendors, reverse_dns).await;
+                                    m.insert(key.clone(), value);
                                 } else {
-                                    m.insert(key.clone(), value.to_json().await);
+                                    let value = value.to_json(&context.vendors, reverse_dns).await;
+                                    m.insert(key.clone(), value);
                                 }
                             }
                             return json!(m);
                         }
                     }
-                    "listen_interfaces" => {
+                    "get_hosts" => {
                         if let Value::String(name) = value {
-                            tokio::spawn(start_statistics_interface(
+                            let map = context.map.lock().await;
+                            if let Some(s) = map.get(&name) {
+                                return s.hosts_to_json();
+                            }
+                        }
+                    }
+                    "get_active_flows" => {
+                        if let Value::String(name) = value {
+                            let map = context.map.lock().await;
+                            if let Some(s) = map.get(&name) {
+                                return s.active_flows_to_json();
+                            }
+                        }
+                    }
+                    "get_closed_flows" => {
+                        if let Value::String(name) = value {
+                            let map = context.map.lock().await;
+                            if let Some(s) = map.get(&name) {
+                                return s.closed_flows_to_json();
+                            }
+                        }
+                    }
+                    "get_neighbors" => {
+                        if let Value::String(name) = value {
+                            let map = context.map.lock().await;
+                            if let Some(s) = map.get(&name) {
+                                return s.neighbors_to_json(&context.vendors);
+                            }
+                        }
+                    }
+                    "get_anomalies" => {
+                        if let Value::String(name) = value {
+                            let map = context.map.lock().await;
+                            if let Some(s) = map.get(&name) {
+                                return s.anomalies_to_json();
+                            }
+                        }
+                    }
+                    "get_alerts" => {
+                        if let (Some(alerts), Some(since)) = (&context.alerts, value.as_u64()) {
+                            return alerts.to_json(Some(since));
+                        }
+                    }
+                    "listen_interfaces" => {
+                        let request = match value {
+                            Value::String(name) => Some(ListenRequest {
+                                name,
+                                config: Default::default(),
+                            }),
+                            value @ Value::Object(_) => serde_json::from_value(value).ok(),
+                            _ => None,
+                        };
+                        if let Some(ListenRequest { name, config }) = request {
+                            let backend = config.backend.unwrap_or(context.capture_backend);
+                            if backend.is_live() && !permitted(context, backend).await {
+                                return context.privileges.permission_denied(&name);
+                            }
+                            context.shutdown.spawn(start_statistics_interface(
                                 name,
-                                context.start_time.clone(),
+                                config,
+                                context.capture_backend,
+                                context.exporter.clone(),
+                                context.start_time,
                                 context.map.clone(),
                             ));
                         }
@@ -100,7 +250,7 @@ async fn handle_request(context: &AppContext, request: Value) -> Value {
                         if let Value::String(name) = value {
                             let mut map = context.map.lock().await;
                             if let Some(s) = map.get_mut(&name) {
-                                s.close();
+                                s.stop();
                             }
                         }
                     }
@@ -108,16 +258,15 @@ async fn handle_request(context: &AppContext, request: Value) -> Value {
                         if let Value::String(name) = value {
                             let mut map = context.map.lock().await;
                             if let Some(mut s) = map.remove(&name) {
-                                s.close();
+                                s.stop();
                             }
                         }
                     }
                     _ => {}
                 }
-                break;
             }
         }
         _ => {}
     }
-    return Value::Null;
+    Value::Null
 }
@@ -613,7 +613,7 @@ function IpAddress({ data }: {
                 return k1[2] - k0[2]
               })
               .map(([ip, speed]) => {
-                return <ListItem key={ip} primaryText={ip}
+                return <ListItem key={ip} primaryText={ip} secondaryText={data.names?.[ip]}
                   meta={<SpeedView output={toSpeed(speed.output[lastTimestamp])} input={toSpeed(speed.input[lastTimestamp])} />}
                   activated={selected === ip && openChart}
                   onClick={() => select(ip)} />
@@ -644,8 +644,8 @@ function MacAddress({ data }: {
       for (const [key, size] of Object.entries(v)) {
         const header = JSON.parse(key) as HeaderType;
         const isOutput = isOut(header, data);
-        ensure(result, header.source, timestamp, size, isOutput);
-        ensure(result, header.destination, timestamp, size, isOutput);
+        if (header.source !== null) ensure(result, header.source, timestamp, size, isOutput);
+        if (header.destination !== null) ensure(result, header.destination, timestamp, size, isOutput);
       }
     }
     const timestamps = data.history.map(([t]) => t);
@@ -695,7 +695,7 @@ function MacAddress({ data }: {
       <ul>
         {Object.entries(macs).map(([mac, sizes]) => {
           return <ListItem key={mac} primaryText={mac}
-            secondaryText={mac === data.mac ? "This interface's MAC address" : undefined}
+            secondaryText={mac === data.mac ? "This interface's MAC address" : data.vendors?.[mac]}
             meta={<SpeedView output={toSpeed(sizes.output[lastTimestamp])} input={toSpeed(sizes.input[lastTimestamp])} />}
             activated={selected === mac && openChart}
             onClick={() => select(mac)} />
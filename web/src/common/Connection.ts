@@ -6,17 +6,115 @@ export type InterfaceDataType = {
     history: ([number, { [header: string]: number }])[],
     closed: boolean,
     mac: string | null,
+    remote?: {
+        exporter: string,
+        if_index: number,
+        protocol: 'sflow' | 'netflow5' | 'netflow9' | 'ipfix',
+    } | null,
+    persistent?: boolean,
+    kernel?: KernelCountersType | null,
+    vendors?: { [mac: string]: string },
+    names?: { [ip: string]: string },
+}
+
+export type StatusType = {
+    version: string,
+    uptime: number,
+    privileges: {
+        root: boolean,
+        net_raw: boolean,
+        net_admin: boolean,
+        dropped: boolean,
+        helper: boolean,
+        seccomp: boolean,
+    },
+    capture_permitted: boolean,
+}
+
+export type KernelCountersType = {
+    rx_bytes: number,
+    rx_packets: number,
+    rx_errors: number,
+    rx_dropped: number,
+    tx_bytes: number,
+    tx_packets: number,
+    tx_errors: number,
+    tx_dropped: number,
 }
 
 export type HeaderType = {
     protocol: number,
-    source: string,
-    destination: string,
+    source: string | null,
+    destination: string | null,
     ip_header: {
         protocol: number,
         source: string,
         destination: string,
+        fragment?: boolean,
     } | null,
+    vlan?: number,
+    inner_vlan?: number,
+    mpls_label?: number,
+    tunnel?: {
+        kind: 'gre' | 'vxlan' | 'geneve' | 'ip_in_ip',
+        source: string,
+        destination: string,
+        vni?: number,
+    },
+    host?: string,
+}
+
+export type AlertType = {
+    rule: string,
+    interface: string,
+    subject?: string,
+    state: 'raised' | 'cleared',
+    value: number,
+    threshold?: number,
+    timestamp: number,
+}
+
+export type AlertsType = {
+    active: AlertType[],
+    history: AlertType[],
+}
+
+export type AnomalyType = {
+    timestamp: number,
+    kind: 'rate_spike' | 'rate_drop' | 'broadcast_storm' | 'new_protocol' | 'port_scan',
+    value?: number,
+    mean?: number,
+    deviation?: number,
+    protocol?: number,
+    ip_protocol?: number,
+    source?: string,
+    ports?: number,
+}
+
+export type InterfaceInfoType = {
+    name: string,
+    index: number,
+    mac: string | null,
+    up: boolean,
+    running: boolean,
+    loopback: boolean,
+    point_to_point: boolean,
+    mtu: number | null,
+    speed: number | null,
+    duplex: 'full' | 'half' | null,
+    addresses: string[],
+    counters: KernelCountersType | null,
+    listening: boolean,
+    permitted: boolean,
+}
+
+export type InterfaceEventType = {
+    kind: 'added' | 'removed' | 'renamed' | 'changed' | 'resumed',
+    name: string,
+    index?: number,
+    up?: boolean,
+    running?: boolean,
+    from?: string,
 }
 
 class Connection {
@@ -29,6 +127,7 @@ class Connection {
     protected ws: WebSocket;
     protected tag = 0;
     protected callbacks: Map<number, (value: unknown) => unknown> = new Map();
+    protected interfaceListeners: Set<(event: InterfaceEventType) => unknown> = new Set();
 
     protected _listener(event: MessageEvent) {
         const e = event as MessageEvent<string>;
@@ -42,6 +141,8 @@ class Connection {
                     callback(obj.response);
                 }
 
+            } else if ('event' in obj && obj.event === 'interface') {
+                this.interfaceListeners.forEach(listener => listener(obj.data));
             }
         }
     }
@@ -69,11 +170,28 @@ class Connection {
     }
 
     async getInterfaces() {
-        const data = await this._request("get_interfaces") as string[];
+        const data = await this._request("get_interfaces") as InterfaceInfoType[];
         if (data === null) throw new Error('No data');
         return data;
     }
 
+    async getStatus() {
+        return await this._request("get_status") as StatusType | null;
+    }
+
+    async getAlerts(since?: number) {
+        return await this._request(since === undefined ? "get_alerts" : { get_alerts: since }) as AlertsType | null;
+    }
+
+    async getAnomalies(interfaceName: string) {
+        return await this._request({ get_anomalies: interfaceName }) as AnomalyType[] | null;
+    }
+
+    onInterfaceEvent(listener: (event: InterfaceEventType) => unknown) {
+        this.interfaceListeners.add(listener);
+        return () => { this.interfaceListeners.delete(listener); };
+    }
+
     listenInterface(interfaceName: string) {
         return this._request({ listen_interfaces: interfaceName });
     }
@@ -91,6 +209,7 @@ class Connection {
         this._listener = function () { };
         this.callbacks.forEach((value) => value(null));
         this.callbacks.clear();
+        this.interfaceListeners.clear();
     }
 }
 
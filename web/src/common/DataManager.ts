@@ -1,5 +1,5 @@
 import React from "react";
-import Connection, { DataType } from "./Connection";
+import Connection, { DataType, InterfaceEventType } from "./Connection";
 
 class DataManager {
     static DataContext = React.createContext(null as unknown as DataType);
@@ -33,6 +33,8 @@ class DataManager {
             const mergeData = value;
             if (oldData) {
                 value.mac ??= oldData.mac;
+                value.vendors = { ...oldData.vendors, ...value.vendors };
+                value.names = { ...oldData.names, ...value.names };
                 const history = mergeData.history;
                 const start = history.findIndex(v => v !== null);
                 switch (start) {
@@ -62,8 +64,13 @@ class DataManager {
         return this.connection.getInterfaces();
     }
 
+    onInterfaceEvent(listener: (event: InterfaceEventType) => unknown) {
+        return this.connection.onInterfaceEvent(listener);
+    }
+
     listenInterface(interfaceName: string) {
-        this.data = { ...this.data, [interfaceName]: { history: [], closed: false, mac: null } }
+        const remote = this.data[interfaceName]?.remote;
+        this.data = { ...this.data, [interfaceName]: { history: [], closed: false, mac: null, remote } }
         this.connection.listenInterface(interfaceName);
         this.callback(this.data);
     }
@@ -1,19 +1,20 @@
 import React from "react";
 import { Button, Checkbox, Dialog, LinearProgress, ListDivider, ListItem, Switch, Typography } from "rmcw";
 import DataManager from "../common/DataManager";
+import { InterfaceInfoType } from "../common/Connection";
 
 function Manage({ open, close }: { open: boolean, close: () => unknown }) {
   const connection = React.useContext(DataManager.Context);
   const [loading, setLoading] = React.useState(false);
-  const [interfaces, setInterfaces] = React.useState([] as string[]);
-  const manager = React.useContext(DataManager.Context);
+  const [interfaces, setInterfaces] = React.useState([] as InterfaceInfoType[]);
   const data = React.useContext(DataManager.DataContext);
-  const { lostConnectionInterfaces, cachedInterfaces } = React.useMemo(() => {
-    const l = new Set(interfaces);
+  const { lostConnectionInterfaces, collectedInterfaces, cachedInterfaces } = React.useMemo(() => {
+    const l = new Set(interfaces.map(v => v.name));
     const entries = Object.entries(data);
     const cachedInterfaces = new Map(entries);
-    const lostConnectionInterfaces = entries.filter(([v]) => !l.has(v));
-    return { lostConnectionInterfaces, cachedInterfaces };
+    const lostConnectionInterfaces = entries.filter(([v, d]) => !l.has(v) && !d.remote);
+    const collectedInterfaces = entries.filter(([, d]) => d.remote).map(([v]) => v);
+    return { lostConnectionInterfaces, collectedInterfaces, cachedInterfaces };
   }, [data, interfaces]);
 
   React.useEffect(() => {
@@ -32,6 +33,14 @@ function Manage({ open, close }: { open: boolean, close: () => unknown }) {
     }
   }, [connection]);
 
+  React.useEffect(() => {
+    if (connection) {
+      return connection.onInterfaceEvent(() => {
+        connection.getInterfaces().then(setInterfaces);
+      });
+    }
+  }, [connection]);
+
   React.useEffect(() => {
     if (open && connection) {
       let signal = false;
@@ -59,32 +68,24 @@ function Manage({ open, close }: { open: boolean, close: () => unknown }) {
       actions={<Button onClick={close}>close</Button>}>
       <LinearProgress closed={!loading} />
       {interfaces.map((v, index) => {
-        const d = cachedInterfaces.get(v);
+        const d = cachedInterfaces.get(v.name);
         const selected = d !== undefined;
-        return <ListItem key={index}
-          graphic={<Checkbox checked={selected} onClick={(e) => {
-            if (manager) {
-              if (selected) {
-                manager.clearInterface(v);
-              } else {
-                manager.listenInterface(v);
-              }
-              e.stopPropagation();
-            }
-
-          }} />}
-          primaryText={v}
-          meta={<Switch selected={d?.closed === false} />}
-          onClick={() => {
-            if (manager) {
-              if (d?.closed === false) {
-                manager.notListenInterface(v);
-              } else {
-                manager.listenInterface(v);
-              }
-            }
-          }} />
+        return <InterfaceItem key={index}
+          name={v.name}
+          info={v}
+          selected={selected}
+          listening={d?.closed === false} />
       })}
+      {collectedInterfaces.length !== 0 ?
+        <>
+          <ListDivider />
+          <Typography.Button>Collected</Typography.Button>
+          {collectedInterfaces.map((v, index) => <InterfaceItem key={index}
+            name={v}
+            selected
+            listening={cachedInterfaces.get(v)?.closed === false} />)}
+        </> :
+        undefined}
       {lostConnectionInterfaces.length !== 0 ?
         <>
           <ListDivider />
@@ -96,4 +97,42 @@ function Manage({ open, close }: { open: boolean, close: () => unknown }) {
   );
 }
 
+function describe(info: InterfaceInfoType) {
+  const parts = [info.up ? (info.running ? 'up' : 'no carrier') : 'down'];
+  if (info.speed !== null) parts.push(`${info.speed} Mbit/s${info.duplex ? ` ${info.duplex} duplex` : ''}`);
+  parts.push(...info.addresses);
+  return parts.join(', ');
+}
+
+function InterfaceItem({ name: v, info, selected, listening }: { name: string, info?: InterfaceInfoType, selected: boolean, listening: boolean }) {
+  const manager = React.useContext(DataManager.Context);
+  // nothing to capture without the permission, unless already listening
+  const disabled = info !== undefined && !info.permitted && !selected;
+  return <ListItem
+    disabled={disabled}
+    secondaryText={info && describe(info)}
+    graphic={<Checkbox checked={selected} onClick={(e) => {
+      if (manager && !disabled) {
+        if (selected) {
+          manager.clearInterface(v);
+        } else {
+          manager.listenInterface(v);
+        }
+        e.stopPropagation();
+      }
+
+    }} />}
+    primaryText={v}
+    meta={<Switch selected={listening} />}
+    onClick={() => {
+      if (manager && !disabled) {
+        if (listening) {
+          manager.notListenInterface(v);
+        } else {
+          manager.listenInterface(v);
+        }
+      }
+    }} />
+}
+
 export default Manage;